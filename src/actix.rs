@@ -0,0 +1,147 @@
+//! [actix-web] integration
+//!
+//! [actix-web]: https://docs.rs/actix-web
+
+use crate::status::Update;
+use crate::{Client, Error};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::web::{self, Bytes};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, Route};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Extractor for a verified status update posted by Paynow to the result URL
+///
+/// The body is verified against the [`Client`] registered as
+/// `web::Data<Client>` application data. Malformed bodies are rejected with
+/// `400 Bad Request` and bodies with an invalid signature with
+/// `401 Unauthorized`.
+#[derive(Debug, Clone)]
+pub struct VerifiedUpdate(pub Update);
+
+impl FromRequest for VerifiedUpdate {
+    type Error = Rejection;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let client = req.app_data::<web::Data<Client>>().cloned();
+        let body = Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let client = client.ok_or(Rejection::MissingClient)?;
+            let body = body.await.map_err(Rejection::Body)?;
+            client
+                .parse_status_update(&body)
+                .map(Self)
+                .map_err(Rejection::Update)
+        })
+    }
+}
+
+/// Rejection used for [`VerifiedUpdate`]
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Rejection {
+    #[error("Paynow client not registered as application data")]
+    MissingClient,
+    #[error("failed to read request body")]
+    Body(#[source] actix_web::Error),
+    #[error("invalid status update")]
+    Update(#[source] Error),
+}
+
+impl ResponseError for Rejection {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingClient => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Body(error) => error.as_response_error().status_code(),
+            Self::Update(error) if error.is_signature_error() => StatusCode::UNAUTHORIZED,
+            Self::Update(..) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Route for the result URL that hands verified status updates to `callback`
+///
+/// Paynow is answered with an empty `200 OK` once the callback succeeds. If
+/// the callback fails, for example because the update could not be
+/// persisted, Paynow is answered with `500 Internal Server Error` so that it
+/// sends the update again later.
+pub fn handler<F, Fut, E>(callback: F) -> Route
+where
+    F: Fn(Update) -> Fut + Clone + 'static,
+    Fut: Future<Output = Result<(), E>> + 'static,
+{
+    web::post().to(move |VerifiedUpdate(update): VerifiedUpdate| {
+        let callback = callback.clone();
+        async move {
+            match callback(update).await {
+                Ok(()) => HttpResponse::Ok().finish(),
+                Err(_) => HttpResponse::InternalServerError().finish(),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, offline_client, update_body};
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+    use std::convert::Infallible;
+
+    async fn status(client: Option<Client>, body: String) -> StatusCode {
+        let route = handler(|_| async { Ok::<_, Infallible>(()) });
+        status_with(route, client, body).await
+    }
+
+    async fn status_with(route: Route, client: Option<Client>, body: String) -> StatusCode {
+        let mut app = App::new().route("/result", route);
+        if let Some(client) = client {
+            app = app.app_data(web::Data::new(client));
+        }
+        let app = init_service(app).await;
+        let request = TestRequest::post()
+            .uri("/result")
+            .set_payload(body)
+            .to_request();
+        call_service(&app, request).await.status()
+    }
+
+    #[tokio::test]
+    async fn accepts_verified_updates() {
+        let client = offline_client(key());
+        let body = update_body(&client);
+        assert_eq!(status(Some(client), body).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_updates() {
+        let status = status(Some(offline_client(key())), "status=Paid".to_owned()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_signatures() {
+        let body = update_body(&offline_client(key()));
+        let status = status(Some(offline_client(key())), body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn requires_a_client() {
+        let body = update_body(&offline_client(key()));
+        let status = status(None, body).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_callback_fails() {
+        let client = offline_client(key());
+        let body = update_body(&client);
+        let route = handler(|_| async { Err("database unavailable") });
+        let status = status_with(route, Some(client), body).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,107 @@
+//! [axum] integration
+//!
+//! [axum]: https://docs.rs/axum
+
+use crate::status::Update;
+use crate::webhook::{self, Rejected};
+use crate::{Client, Error};
+use ::axum::extract::{FromRef, FromRequest, Request};
+use ::axum::http::StatusCode;
+use ::axum::response::{IntoResponse, Response};
+
+/// Extractor for a verified status update posted by Paynow to the result URL
+///
+/// The body is verified against the [`Client`] in the router state, which
+/// must implement [`FromRef`] for it. Bodies larger than 16 KiB are rejected
+/// with `413 Payload Too Large`, malformed ones with `400 Bad Request` and
+/// ones with an invalid signature with `401 Unauthorized`.
+#[derive(Debug, Clone)]
+pub struct VerifiedUpdate(pub Update);
+
+impl<S> FromRequest<S> for VerifiedUpdate
+where
+    Client: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Rejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let client = Client::from_ref(state);
+        match webhook::read_update(&client, req.into_body()).await {
+            Ok(update) => Ok(Self(update)),
+            Err(Rejected::Body(status)) => Err(Rejection::Body(status)),
+            Err(Rejected::Update(error)) => Err(Rejection::Update(error)),
+        }
+    }
+}
+
+/// Rejection used for [`VerifiedUpdate`]
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Rejection {
+    #[error("failed to read request body")]
+    Body(StatusCode),
+    #[error("invalid status update")]
+    Update(#[source] Error),
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Body(status) => status.into_response(),
+            Self::Update(error) => {
+                let status = if error.is_signature_error() {
+                    StatusCode::UNAUTHORIZED
+                } else {
+                    StatusCode::BAD_REQUEST
+                };
+                (status, error.to_string()).into_response()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, offline_client, update_body};
+    use crate::webhook::MAX_BODY;
+    use ::axum::body::Body;
+    use ::axum::routing::post;
+    use ::axum::Router;
+    use tower_service::Service;
+
+    async fn status(client: Client, body: String) -> StatusCode {
+        let mut router = Router::new()
+            .route("/result", post(|_: VerifiedUpdate| async {}))
+            .with_state(client);
+        let request = Request::post("/result").body(Body::from(body)).unwrap();
+        router.call(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn accepts_verified_updates() {
+        let client = offline_client(key());
+        let body = update_body(&client);
+        assert_eq!(status(client, body).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_updates() {
+        let status = status(offline_client(key()), "status=Paid".to_owned()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_signatures() {
+        let body = update_body(&offline_client(key()));
+        let status = status(offline_client(key()), body).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_bodies() {
+        let status = status(offline_client(key()), "a".repeat(MAX_BODY + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -0,0 +1,354 @@
+//! Blocking Paynow client
+//!
+//! This mirrors the async [`Client`](crate::Client) for applications that
+//! don't run an async runtime. Requests are hashed and responses validated
+//! exactly the same way as they are by the async client.
+
+use crate::payment::express::{self, Outcome};
+use crate::payment::Payment;
+use crate::retry::is_transient;
+use crate::status::Update;
+use crate::test_mode::Environment;
+use crate::transport::{Error as TransportError, Request, Response};
+use crate::{ApiKey, ClientBuilder, Config, Error, KeyMatch};
+use rust_decimal::Decimal;
+use std::fmt;
+use std::sync::Arc;
+use url::Url;
+
+/// Blocking HTTP transport used by [`Client`] to talk to Paynow
+///
+/// See [`transport::Transport`](crate::transport::Transport) for the
+/// requirements a transport has to meet.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send a request and return the response
+    fn post(&self, request: Request) -> Result<Response, TransportError>;
+}
+
+#[cfg(feature = "reqwest")]
+impl Transport for reqwest::blocking::Client {
+    fn post(&self, request: Request) -> Result<Response, TransportError> {
+        use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+
+        let mut builder = reqwest::blocking::Client::post(self, request.url);
+        builder = match request.form {
+            Some(form) => builder
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(form),
+            None => builder.header(CONTENT_LENGTH, 0),
+        };
+        let response = builder
+            .send()
+            .map_err(|e| TransportError::Send(Box::new(e)))?;
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .map_err(|e| TransportError::Body(Box::new(e)))?;
+        Ok(Response { status, body })
+    }
+}
+
+/// Blocking Paynow client
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub(crate) config: Config,
+    pub(crate) transport: Arc<dyn Transport>,
+}
+
+impl Client {
+    /// Create new client
+    #[cfg(feature = "reqwest")]
+    #[must_use]
+    pub fn new(id: u64, key: ApiKey) -> Self {
+        Self {
+            config: Config::new(id, key),
+            transport: Arc::new(reqwest::blocking::Client::new()),
+        }
+    }
+
+    /// Create a client builder
+    ///
+    /// Use [`ClientBuilder::build_blocking`] to build the client.
+    #[must_use]
+    pub fn builder(id: u64, key: ApiKey) -> ClientBuilder {
+        ClientBuilder::new(id, key)
+    }
+
+    /// Initiate a payment
+    #[must_use]
+    pub fn payment<'a>(
+        &self,
+        reference: &'a str,
+        amount: Decimal,
+        return_url: &'a Url,
+        result_url: &'a Url,
+    ) -> Payment<'a> {
+        self.config
+            .payment(reference, amount, return_url, result_url)
+    }
+
+    /// Initiate an express payment
+    #[must_use]
+    pub fn express_payment<'a>(
+        &self,
+        method: express::Method<'a>,
+        reference: &'a str,
+        amount: Decimal,
+        result_url: &'a Url,
+        auth_email: &'a str,
+        merchant_trace: &'a str,
+    ) -> express::Payment<'a> {
+        self.config.express_payment(
+            method,
+            reference,
+            amount,
+            result_url,
+            auth_email,
+            merchant_trace,
+        )
+    }
+
+    /// Get status of a payment
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when ID is invalid, funding source has insufficient funds etc
+    pub fn poll_status(&self, poll_url: Url) -> Result<Update, Error> {
+        self.config.retry.run_blocking(true, || {
+            let request = self.config.poll_request(poll_url.clone());
+            let response = self.transport.post(request)?;
+            self.config.poll_response(response)
+        })
+    }
+
+    /// Lookup payment status
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the trace ID is not found
+    pub fn trace_payment(&self, merchant_trace: &str) -> Result<Update, Error> {
+        self.config.retry.run_blocking(true, || {
+            let request = self.config.trace_request(merchant_trace)?;
+            let response = self.transport.post(request)?;
+            self.config.trace_response(merchant_trace, response)
+        })
+    }
+
+    /// Submit an express payment, recovering the transaction if the outcome
+    /// of the request is unknown
+    ///
+    /// See [`express::Payment::submit_or_recover`].
+    ///
+    /// # Errors
+    ///
+    /// Returns the original error if Paynow has no record of the payment,
+    /// which isn't proof that the payment never reached Paynow. If the lookup
+    /// itself fails its error is returned and the state of the payment is
+    /// still unknown.
+    pub fn submit_or_recover(&self, payment: &express::Payment<'_>) -> Result<Outcome, Error> {
+        match initiate(self, payment) {
+            Ok(response) => Ok(Outcome::Initiated(response)),
+            Err(error) => match payment.merchant_trace() {
+                Some(merchant_trace) if is_transient(&error) => {
+                    express::recover(error, self.trace_payment(merchant_trace))
+                }
+                _ => Err(error),
+            },
+        }
+    }
+
+    /// Get the environment the client was configured for, if any
+    #[must_use]
+    pub fn environment(&self) -> Option<Environment> {
+        self.config.environment
+    }
+
+    /// Parse and verify a status update posted by Paynow to the result URL
+    ///
+    /// See [`crate::Client::parse_status_update`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the body is malformed or its signature is invalid
+    pub fn parse_status_update(&self, body: &[u8]) -> Result<Update, Error> {
+        self.config.parse_status_update(body)
+    }
+
+    /// Sign a Paynow message
+    ///
+    /// See [`crate::Client::sign`].
+    #[must_use]
+    pub fn sign(&self, fields: &[(&str, &str)]) -> String {
+        self.config.sign(fields)
+    }
+
+    /// Verify the `hash` field of a Paynow message
+    ///
+    /// See [`crate::Client::verify`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no `hash` field or the hash is invalid
+    pub fn verify(&self, fields: &[(&str, &str)]) -> Result<KeyMatch, Error> {
+        self.config.verify(fields)
+    }
+
+    /// Validate a status update, returning the integration key that signed it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hash is invalid
+    pub fn validate(&self, update: &Update) -> Result<KeyMatch, Error> {
+        update.validate_with(&self.config)
+    }
+}
+
+/// Message that can be submitted to Paynow using a blocking client
+pub trait Submit {
+    type Response;
+
+    /// Submits the message to Paynow
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the request fails or Paynow rejects the message
+    fn submit(self, client: &Client) -> Result<Self::Response, Error>;
+}
+
+impl Submit for &'_ Payment<'_> {
+    type Response = crate::payment::Response;
+
+    fn submit(self, client: &Client) -> Result<Self::Response, Error> {
+        client.config.retry.run_blocking(false, || {
+            let request = self.request(&client.config)?;
+            let response = client.transport.post(request)?;
+            self.response(&client.config, response)
+        })
+    }
+}
+
+impl Submit for &'_ express::Payment<'_> {
+    type Response = express::Response;
+
+    fn submit(self, client: &Client) -> Result<Self::Response, Error> {
+        client
+            .config
+            .retry
+            .run_blocking(false, || initiate(client, self))
+    }
+}
+
+/// Send an express payment to Paynow once
+fn initiate(client: &Client, payment: &express::Payment<'_>) -> Result<express::Response, Error> {
+    let request = payment.request(&client.config)?;
+    let response = client.transport.post(request)?;
+    payment.response(&client.config, response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::Fields;
+    use crate::test_util::key;
+    use crate::HASH;
+    use std::sync::Mutex;
+
+    const POLL_URL: &str = "https://www.paynow.co.zw/Interface/CheckPayment/?guid=1";
+
+    const UPDATE: [(&str, &str); 5] = [
+        ("reference", "Order 1"),
+        ("paynowreference", "1"),
+        ("amount", "10.00"),
+        ("status", "Paid"),
+        ("pollurl", POLL_URL),
+    ];
+
+    /// Transport answering every request with the same fields, signed with
+    /// the key of `config`, and keeping the forms it was sent
+    #[derive(Debug)]
+    struct Paynow {
+        config: Config,
+        fields: &'static [(&'static str, &'static str)],
+        forms: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Transport for Paynow {
+        fn post(&self, request: Request) -> Result<Response, TransportError> {
+            self.forms.lock().unwrap().extend(request.form);
+            let hash = self.config.sign(self.fields);
+            let mut fields = self.fields.to_vec();
+            fields.push((HASH, &hash));
+            Ok(Response {
+                status: 200,
+                body: serde_urlencoded::to_string(fields).unwrap(),
+            })
+        }
+    }
+
+    /// Client answered with `fields` signed with `signing_key`, along with
+    /// the forms it sends
+    fn client(
+        key: ApiKey,
+        signing_key: ApiKey,
+        fields: &'static [(&'static str, &'static str)],
+    ) -> (Client, Arc<Mutex<Vec<String>>>) {
+        let forms = Arc::default();
+        let client = Client::builder(1, key)
+            .blocking_transport(Paynow {
+                config: ClientBuilder::new(1, signing_key).config(),
+                fields,
+                forms: Arc::clone(&forms),
+            })
+            .build_blocking()
+            .unwrap();
+        (client, forms)
+    }
+
+    #[test]
+    fn polls_status() {
+        let key = key();
+        let (client, _) = client(key.clone(), key, &UPDATE);
+        let update = client.poll_status(Url::parse(POLL_URL).unwrap()).unwrap();
+        assert_eq!(update.reference(), "Order 1");
+        assert_eq!(update.status(), &crate::status::Status::Paid);
+    }
+
+    #[test]
+    fn traces_payments() {
+        let key = key();
+        let (client, forms) = client(key.clone(), key, &UPDATE);
+        let update = client.trace_payment("trace-1").unwrap();
+        assert_eq!(update.paynow_reference(), 1);
+        let form = Fields::parse(forms.lock().unwrap()[0].as_bytes());
+        assert!(form.pairs().contains(&("merchanttrace", "trace-1")));
+    }
+
+    #[test]
+    fn submits_signed_payments() {
+        const RESPONSE: [(&str, &str); 3] = [
+            ("status", "Ok"),
+            (
+                "browserurl",
+                "https://www.paynow.co.zw/Payment/ConfirmPayment/1",
+            ),
+            ("pollurl", POLL_URL),
+        ];
+        let key = key();
+        let (client, forms) = client(key.clone(), key, &RESPONSE);
+        let url = Url::parse("https://example.com/result").unwrap();
+        let payment = client.payment("Order 1", Decimal::TEN, &url, &url);
+        let response = payment.submit(&client).unwrap();
+        assert_eq!(response.poll_url().as_str(), POLL_URL);
+        let form = Fields::parse(forms.lock().unwrap()[0].as_bytes());
+        assert!(client.config.verify(&form.pairs()).is_ok());
+    }
+
+    #[test]
+    fn rejects_hash_mismatches() {
+        let (client, _) = client(key(), key(), &UPDATE);
+        assert!(matches!(
+            client.poll_status(Url::parse(POLL_URL).unwrap()),
+            Err(Error::HashMismatch(..))
+        ));
+    }
+}
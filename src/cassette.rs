@@ -0,0 +1,373 @@
+//! Record and replay interactions with Paynow
+//!
+//! A [`Recorder`] wraps the transport of a client that talks to Paynow and
+//! keeps every request it sends along with the response. Once saved, the
+//! resulting [`Cassette`] can be served by a [`Player`] so that the same
+//! payments, polls and traces run without a network connection.
+//!
+//! Hashes, card details and payment tokens are redacted from requests and
+//! responses before they are recorded. Since that leaves responses unsigned,
+//! the player signs them again with the key of the client replaying them,
+//! which doesn't have to be the key they were recorded with.
+
+use crate::form::Fields;
+use crate::transport::{Error, Request, Response, Transport};
+use crate::{ApiKey, ClientBuilder, Config, HASH};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use url::Url;
+
+/// Value recorded in place of a redacted field
+const REDACTED: &str = "[REDACTED]";
+
+/// Request and response fields that are redacted
+const SECRET_FIELDS: [&str; 5] = [HASH, "cardnumber", "cardcvv", "cardexpiry", "token"];
+
+/// Recorded interactions, in the order they happened
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Load a cassette saved with [`Cassette::save`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or is not a cassette
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let json = fs::read(path).map_err(crate::Error::Io)?;
+        serde_json::from_slice(&json).map_err(crate::Error::Cassette)
+    }
+
+    /// Save the cassette as JSON
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        let json = serde_json::to_vec_pretty(self).map_err(crate::Error::Cassette)?;
+        fs::write(path, json).map_err(crate::Error::Io)
+    }
+
+    /// Get a reference to the recorded interactions
+    #[must_use]
+    pub fn interactions(&self) -> &[Interaction] {
+        &self.interactions
+    }
+}
+
+/// Request sent to Paynow and the response it got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    url: Url,
+    form: Option<String>,
+    status: u16,
+    body: String,
+}
+
+impl Interaction {
+    /// Get a reference to the URL the request was sent to
+    #[must_use]
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Get a reference to the redacted form body of the request
+    #[must_use]
+    pub fn form(&self) -> Option<&str> {
+        self.form.as_deref()
+    }
+
+    /// Get HTTP status code of the response
+    #[must_use]
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// Get a reference to the redacted response body
+    #[must_use]
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+}
+
+/// Transport that records the interactions of another transport
+///
+/// Clones share the same cassette, so a clone can be kept to save it after
+/// the original has been handed to a [`ClientBuilder`].
+#[derive(Debug)]
+pub struct Recorder<T> {
+    inner: Arc<T>,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl<T> Clone for Recorder<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            cassette: self.cassette.clone(),
+        }
+    }
+}
+
+impl<T> Recorder<T> {
+    /// Record the interactions of `inner`
+    #[must_use]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            cassette: Arc::default(),
+        }
+    }
+
+    /// Get the interactions recorded so far
+    #[must_use]
+    pub fn cassette(&self) -> Cassette {
+        lock(&self.cassette).clone()
+    }
+
+    /// Save the interactions recorded so far
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be written
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), crate::Error> {
+        self.cassette().save(path)
+    }
+
+    fn record(&self, request: Request, response: &Response) {
+        let interaction = Interaction {
+            url: request.url,
+            form: request
+                .form
+                .map(|form| redact(&form, &SECRET_FIELDS).unwrap_or(form)),
+            status: response.status,
+            body: redact(&response.body, &SECRET_FIELDS).unwrap_or_else(|| response.body.clone()),
+        };
+        lock(&self.cassette).interactions.push(interaction);
+    }
+}
+
+#[async_trait]
+impl<T: Transport> Transport for Recorder<T> {
+    async fn post(&self, request: Request) -> Result<Response, Error> {
+        let response = self.inner.post(request.clone()).await?;
+        self.record(request, &response);
+        Ok(response)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl<T: crate::blocking::Transport> crate::blocking::Transport for Recorder<T> {
+    fn post(&self, request: Request) -> Result<Response, Error> {
+        let response = self.inner.post(request.clone())?;
+        self.record(request, &response);
+        Ok(response)
+    }
+}
+
+/// Transport that serves the interactions of a cassette in order
+///
+/// A request fails to send if it's not for the URL of the next interaction
+/// or when the cassette has run out of interactions.
+#[derive(Debug)]
+pub struct Player {
+    config: Config,
+    interactions: Mutex<std::vec::IntoIter<Interaction>>,
+}
+
+impl Player {
+    /// Replay a cassette, signing responses with `key`
+    #[must_use]
+    pub fn new(cassette: Cassette, key: ApiKey) -> Self {
+        Self {
+            config: ClientBuilder::new(0, key).config(),
+            interactions: Mutex::new(cassette.interactions.into_iter()),
+        }
+    }
+
+    /// Load a cassette and replay it, signing responses with `key`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file can't be read or is not a cassette
+    pub fn load(path: impl AsRef<Path>, key: ApiKey) -> Result<Self, crate::Error> {
+        Ok(Self::new(Cassette::load(path)?, key))
+    }
+
+    fn replay(&self, request: &Request) -> Result<Response, Error> {
+        let interaction = lock(&self.interactions)
+            .next()
+            .ok_or_else(|| Error::Send("cassette has no more interactions".into()))?;
+        if interaction.url != request.url {
+            return Err(Error::Send(
+                format!(
+                    "request to {} does not match cassette, expected {}",
+                    request.url, interaction.url
+                )
+                .into(),
+            ));
+        }
+        Ok(Response {
+            status: interaction.status,
+            body: self.resign(interaction.body),
+        })
+    }
+
+    /// Sign a response again if it was signed when it was recorded
+    fn resign(&self, body: String) -> String {
+        let fields = Fields::parse(body.as_bytes());
+        let pairs = fields.pairs();
+        if !pairs.iter().any(|(name, _)| *name == HASH) {
+            return body;
+        }
+        let mut signed = Fields::default();
+        for (name, value) in pairs.iter().filter(|(name, _)| *name != HASH) {
+            signed.push(name, (*value).to_owned());
+        }
+        let hash = self.config.sign(&signed.pairs());
+        signed.push(HASH, hash);
+        signed.encode()
+    }
+}
+
+#[async_trait]
+impl Transport for Player {
+    async fn post(&self, request: Request) -> Result<Response, Error> {
+        self.replay(&request)
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl crate::blocking::Transport for Player {
+    fn post(&self, request: Request) -> Result<Response, Error> {
+        self.replay(&request)
+    }
+}
+
+/// Replace the values of `names` in a form, returning `None` if it has none
+/// of those fields and can be recorded as is
+fn redact(form: &str, names: &[&str]) -> Option<String> {
+    let fields = Fields::parse(form.as_bytes());
+    let pairs = fields.pairs();
+    if !pairs.iter().any(|(name, _)| names.contains(name)) {
+        return None;
+    }
+    let mut redacted = Fields::default();
+    for (name, value) in pairs {
+        let value = if names.contains(&name) {
+            REDACTED
+        } else {
+            value
+        };
+        redacted.push(name, value.to_owned());
+    }
+    Some(redacted.encode())
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // the data is always left in a consistent state
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct Reply(&'static str);
+
+    #[async_trait]
+    impl Transport for Reply {
+        async fn post(&self, _: Request) -> Result<Response, Error> {
+            Ok(Response {
+                status: 200,
+                body: self.0.to_owned(),
+            })
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_cassettes() {
+        let path = std::env::temp_dir().join(format!("{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, "{").unwrap();
+        let result = Cassette::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(crate::Error::Cassette(..))));
+    }
+
+    #[test]
+    fn redacts_tokens_in_responses() {
+        let recorder = Recorder::new(Reply(
+            "reference=1&paynowreference=1&amount=1.00&status=Paid\
+             &pollurl=https%3a%2f%2fwww.paynow.co.zw%2fInterface%2fCheckPayment%2f%3fguid%3d1\
+             &token=secret-token&tokenexpiry=05Mar2026&hash=ABCDEF",
+        ));
+        let request = Request {
+            url: Url::parse("https://www.paynow.co.zw/Interface/CheckPayment/?guid=1").unwrap(),
+            form: None,
+        };
+        futures_executor::block_on(recorder.post(request)).unwrap();
+        let cassette = recorder.cassette();
+        let body = cassette.interactions()[0].body();
+        assert!(!body.contains("secret-token"));
+        assert!(!body.contains("ABCDEF"));
+        assert!(body.contains("tokenexpiry=05Mar2026"));
+    }
+
+    #[cfg(all(feature = "mock-server", feature = "reqwest"))]
+    #[tokio::test]
+    async fn replays_recorded_interactions() {
+        use crate::payment::express::Method;
+        use crate::payment::Submit;
+        use crate::status::{Status, Update};
+        use crate::test_util::key;
+        use crate::Client;
+        use rust_decimal::Decimal;
+
+        async fn run(client: &Client) -> (Update, Update) {
+            let result_url = Url::parse("http://localhost/result").unwrap();
+            let payment = client.express_payment(
+                Method::eco_cash("0771111111"),
+                "Order 1",
+                Decimal::TEN,
+                &result_url,
+                "customer@example.com",
+                "trace-1",
+            );
+            let response = payment.submit(client).await.unwrap();
+            let polled = client.poll_status(response.take_poll_url()).await.unwrap();
+            let traced = client.trace_payment("trace-1").await.unwrap();
+            (polled, traced)
+        }
+
+        let server = crate::mock_server::start(1, key()).await.unwrap();
+        let recorder = Recorder::new(reqwest::Client::new());
+        let client = server
+            .client_builder()
+            .transport(recorder.clone())
+            .build()
+            .unwrap();
+        let (polled, traced) = run(&client).await;
+        assert_eq!(polled.status(), &Status::Sent);
+        assert_eq!(traced.paynow_reference(), polled.paynow_reference());
+        let base_url = server.base_url().clone();
+        drop(server);
+
+        // the cassette is replayed offline by a client with a different key
+        let key = key();
+        let client = Client::builder(1, key.clone())
+            .base_url(base_url)
+            .transport(Player::new(recorder.cassette(), key))
+            .build()
+            .unwrap();
+        let (replayed, retraced) = run(&client).await;
+        assert_eq!(replayed.status(), polled.status());
+        assert_eq!(replayed.paynow_reference(), polled.paynow_reference());
+        assert_eq!(retraced.paynow_reference(), traced.paynow_reference());
+    }
+}
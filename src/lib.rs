@@ -13,21 +13,57 @@
 //!
 //! [examples]: https://github.com/rushmorem/paynow/tree/main/examples
 
+#[cfg(feature = "actix")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+#[cfg(feature = "cassette")]
+pub mod cassette;
+mod form;
+#[cfg(feature = "listener")]
+pub mod listener;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
 pub mod payment;
+pub mod retry;
 pub mod status;
+pub mod test_mode;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "tower")]
+pub mod tower;
+pub mod transport;
+pub mod watch;
+#[cfg(any(feature = "axum", feature = "listener", feature = "tower"))]
+mod webhook;
 
+use form::Fields;
+use futures_core::Stream;
 use payment::error::{Error as PaymentError, Response};
 use payment::{express, Payment};
-use reqwest::header::CONTENT_LENGTH;
+use retry::RetryPolicy;
 use rust_decimal::Decimal;
 use secrecy::{CloneableSecret, DebugSecret, ExposeSecret, Secret, SerializableSecret, Zeroize};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sha2::digest::Output;
 use sha2::{Digest, Sha512};
 use status::{MerchantTrace, Update};
-use std::fmt::Arguments;
+use std::fmt::{self, Arguments};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use subtle::ConstantTimeEq;
+use test_mode::Environment;
+use transport::{BoxError, Transport};
 use url::Url;
 use uuid::Uuid;
+use watch::WatchPolicy;
+
+const BASE_URL: &str = "https://www.paynow.co.zw/interface/";
 
 /// Paynow API key
 pub type ApiKey = Secret<Key>;
@@ -52,6 +88,16 @@ impl CloneableSecret for Key {}
 impl DebugSecret for Key {}
 impl SerializableSecret for Key {}
 
+/// Integration key that matched the hash of a message
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum KeyMatch {
+    /// The message was signed with the primary key
+    Primary,
+    /// The message was signed with the previous key at this index, in the
+    /// order the previous keys were added to the client
+    Previous(usize),
+}
+
 #[derive(Clone, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
 struct Hash(String);
 
@@ -68,26 +114,27 @@ impl SerializableSecret for Hash {}
 /// Paynow client
 #[derive(Debug, Clone)]
 pub struct Client {
-    id: u64,
-    key: ApiKey,
-    req: reqwest::Client,
-    base: Url,
+    config: Config,
+    transport: Arc<dyn Transport>,
 }
 
 impl Client {
     /// Create new client
-    #[allow(clippy::missing_panics_doc)]
+    #[cfg(feature = "reqwest")]
     #[must_use]
     pub fn new(id: u64, key: ApiKey) -> Self {
         Self {
-            id,
-            key,
-            req: reqwest::Client::new(),
-            // we know this is a valid URL so this should never panic
-            base: Url::parse("https://www.paynow.co.zw/interface/").unwrap(),
+            config: Config::new(id, key),
+            transport: Arc::new(reqwest::Client::new()),
         }
     }
 
+    /// Create a client builder
+    #[must_use]
+    pub fn builder(id: u64, key: ApiKey) -> ClientBuilder {
+        ClientBuilder::new(id, key)
+    }
+
     /// Initiate a payment
     #[must_use]
     pub fn payment<'a>(
@@ -96,6 +143,155 @@ impl Client {
         amount: Decimal,
         return_url: &'a Url,
         result_url: &'a Url,
+    ) -> Payment<'a> {
+        self.config
+            .payment(reference, amount, return_url, result_url)
+    }
+
+    /// Initiate an express payment
+    #[must_use]
+    pub fn express_payment<'a>(
+        &self,
+        method: express::Method<'a>,
+        reference: &'a str,
+        amount: Decimal,
+        result_url: &'a Url,
+        auth_email: &'a str,
+        merchant_trace: &'a str,
+    ) -> express::Payment<'a> {
+        self.config.express_payment(
+            method,
+            reference,
+            amount,
+            result_url,
+            auth_email,
+            merchant_trace,
+        )
+    }
+
+    /// Get status of a payment
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when ID is invalid, funding source has insufficient funds etc
+    pub async fn poll_status(&self, poll_url: Url) -> Result<Update, Error> {
+        self.config
+            .retry
+            .run(true, || async {
+                let request = self.config.poll_request(poll_url.clone());
+                let response = self.transport.post(request).await?;
+                self.config.poll_response(response)
+            })
+            .await
+    }
+
+    /// Lookup payment status
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the trace ID is not found
+    pub async fn trace_payment(&self, merchant_trace: &str) -> Result<Update, Error> {
+        self.config
+            .retry
+            .run(true, || async {
+                let request = self.config.trace_request(merchant_trace)?;
+                let response = self.transport.post(request).await?;
+                self.config.trace_response(merchant_trace, response)
+            })
+            .await
+    }
+
+    /// Poll a payment until it is paid or reaches a final status
+    ///
+    /// The stream yields an update whenever the status of the payment
+    /// changes and ends once the status is [paid](status::Status::is_paid)
+    /// or [final](status::Status::is_final). If that doesn't happen before the
+    /// policy's timeout, for example because the customer never responded to
+    /// the USSD prompt, the stream ends with
+    /// [`Error::WatchTimeout`]. Transient errors are yielded without ending
+    /// the stream, any other error ends it.
+    pub fn watch(
+        &self,
+        poll_url: Url,
+        policy: WatchPolicy,
+    ) -> impl Stream<Item = Result<Update, Error>> + Send + Unpin {
+        watch::watch(self.clone(), poll_url, policy)
+    }
+
+    /// Get the environment the client was configured for, if any
+    #[must_use]
+    pub fn environment(&self) -> Option<Environment> {
+        self.config.environment
+    }
+
+    /// Parse and verify a status update posted by Paynow to the result URL
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::MalformedUpdate`] if the body is not a valid status
+    /// update, or [`Error::MissingHash`] or [`Error::HashMismatch`] if its
+    /// signature is invalid
+    pub fn parse_status_update(&self, body: &[u8]) -> Result<Update, Error> {
+        self.config.parse_status_update(body)
+    }
+
+    /// Sign a Paynow message
+    ///
+    /// The hash is the uppercase hex encoded SHA-512 digest of the values of
+    /// the fields, concatenated in order, followed by the integration key.
+    /// A field named `hash` is skipped.
+    #[must_use]
+    pub fn sign(&self, fields: &[(&str, &str)]) -> String {
+        self.config.sign(fields)
+    }
+
+    /// Verify the `hash` field of a Paynow message
+    ///
+    /// The fields must be in the order they were received in.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no `hash` field or the hash is invalid
+    pub fn verify(&self, fields: &[(&str, &str)]) -> Result<KeyMatch, Error> {
+        self.config.verify(fields)
+    }
+}
+
+/// Settings, hashing and response handling shared by the async and blocking clients
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+    pub(crate) id: u64,
+    key: ApiKey,
+    previous_keys: Vec<ApiKey>,
+    grace_period_end: Option<SystemTime>,
+    pub(crate) base: Url,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) environment: Option<Environment>,
+    pub(crate) merchant_email: Option<String>,
+}
+
+impl Config {
+    #[cfg(feature = "reqwest")]
+    fn new(id: u64, key: ApiKey) -> Self {
+        Self {
+            id,
+            key,
+            previous_keys: Vec::new(),
+            grace_period_end: None,
+            // we know this is a valid URL so this should never panic
+            base: Url::parse(BASE_URL).unwrap(),
+            retry: RetryPolicy::none(),
+            environment: None,
+            merchant_email: None,
+        }
+    }
+
+    pub(crate) fn payment<'a>(
+        &self,
+        reference: &'a str,
+        amount: Decimal,
+        return_url: &'a Url,
+        result_url: &'a Url,
     ) -> Payment<'a> {
         Payment {
             amount,
@@ -111,9 +307,7 @@ impl Client {
         }
     }
 
-    /// Initiate an express payment
-    #[must_use]
-    pub fn express_payment<'a>(
+    pub(crate) fn express_payment<'a>(
         &self,
         method: express::Method<'a>,
         reference: &'a str,
@@ -137,145 +331,513 @@ impl Client {
         express::Payment { payment, method }
     }
 
-    /// Get status of a payment
-    ///
-    /// # Errors
-    ///
-    /// Returns an error when ID is invalid, funding source has insufficient funds etc
-    pub async fn poll_status(&self, poll_url: Url) -> Result<Update, Error> {
-        let status = self
-            .submit::<(), Update>(poll_url, Payload::Empty)
-            .await
-            .map_err(|err| match err {
-                Error::UnexpectedResponse(error, msg) => {
-                    match serde_urlencoded::from_str::<'_, Response>(&msg) {
-                        Ok(res) => match PaymentError::from(res) {
-                            PaymentError::InvalidId => Error::InvalidId(self.id),
-                            PaymentError::InsufficientBalance => Error::InsufficientBalance,
-                            PaymentError::Response(msg) => {
-                                Error::Response(reqwest::StatusCode::OK, msg)
-                            }
-                            _ => Error::UnexpectedResponse(error, msg),
-                        },
-                        Err(..) => Error::UnexpectedResponse(error, msg),
-                    }
+    pub(crate) fn poll_request(&self, poll_url: Url) -> transport::Request {
+        transport::Request {
+            url: poll_url,
+            form: None,
+        }
+    }
+
+    pub(crate) fn poll_response(&self, response: transport::Response) -> Result<Update, Error> {
+        self.parse::<Update>(response).map_err(|err| match err {
+            Error::UnexpectedResponse(error, msg) => {
+                match serde_urlencoded::from_str::<'_, Response>(&msg) {
+                    Ok(res) => match PaymentError::from(res) {
+                        PaymentError::InvalidId => Error::InvalidId(self.id),
+                        PaymentError::InsufficientBalance => Error::InsufficientBalance,
+                        PaymentError::Response(msg) => Error::Response(200, msg),
+                        _ => Error::UnexpectedResponse(error, msg),
+                    },
+                    Err(..) => Error::UnexpectedResponse(error, msg),
                 }
-                error => error,
-            })?;
-        status.validate(self)?;
-        Ok(status)
+            }
+            error => error,
+        })
     }
 
-    /// Lookup payment status
-    ///
-    /// # Errors
-    ///
-    /// Returns an error when the trace ID is not found
-    pub async fn trace_payment(&self, merchant_trace: &str) -> Result<Update, Error> {
-        #[derive(Deserialize)]
-        struct NotFound {
-            status: status::NotFound,
-            hash: Secret<Hash>,
-        }
-        let id = self.id;
-        let status = status::Message;
+    pub(crate) fn trace_request(&self, merchant_trace: &str) -> Result<transport::Request, Error> {
         let trace = MerchantTrace {
-            id,
-            status,
             merchant_trace,
-            hash: self.hash(format_args!(
-                "{id}{merchant_trace}{status}",
-                id = id,
-                merchant_trace = merchant_trace,
-                status = status
-            )),
+            id: self.id,
+            status: status::Message,
         };
         let endpoint = self
             .base
             .join("trace")
             .map_err(Error::InvalidTracePaymentUrl)?;
-        let status = self
-            .submit::<_, Update>(endpoint, Payload::Form(&trace))
-            .await
-            .map_err(|err| match err {
-                Error::UnexpectedResponse(error, msg) => {
-                    if let Ok(error) = serde_urlencoded::from_str::<'_, NotFound>(&msg) {
-                        return match self
-                            .validate_hash(&error.hash, format_args!("{}", error.status))
-                        {
-                            Ok(_) => Error::NotFound(merchant_trace.to_owned()),
-                            Err(error) => error,
-                        };
-                    }
-                    match serde_urlencoded::from_str::<'_, Response>(&msg) {
-                        Ok(res) => match PaymentError::from(res) {
-                            PaymentError::InvalidId => Error::InvalidId(self.id),
-                            PaymentError::InsufficientBalance => Error::InsufficientBalance,
-                            PaymentError::Response(msg) => {
-                                Error::Response(reqwest::StatusCode::OK, msg)
-                            }
-                            _ => Error::UnexpectedResponse(error, msg),
-                        },
-                        Err(..) => Error::UnexpectedResponse(error, msg),
-                    }
+        self.form(endpoint, &trace)
+    }
+
+    pub(crate) fn trace_response(
+        &self,
+        merchant_trace: &str,
+        response: transport::Response,
+    ) -> Result<Update, Error> {
+        #[derive(Deserialize)]
+        struct NotFound {
+            #[allow(dead_code)]
+            status: status::NotFound,
+        }
+        self.parse::<Update>(response).map_err(|err| match err {
+            Error::UnexpectedResponse(error, msg) => {
+                if serde_urlencoded::from_str::<'_, NotFound>(&msg).is_ok() {
+                    return match self.verify(&Fields::parse(msg.as_bytes()).pairs()) {
+                        Ok(_) => Error::NotFound(merchant_trace.to_owned()),
+                        Err(error) => error,
+                    };
+                }
+                match serde_urlencoded::from_str::<'_, Response>(&msg) {
+                    Ok(res) => match PaymentError::from(res) {
+                        PaymentError::InvalidId => Error::InvalidId(self.id),
+                        PaymentError::InsufficientBalance => Error::InsufficientBalance,
+                        PaymentError::Response(msg) => Error::Response(200, msg),
+                        _ => Error::UnexpectedResponse(error, msg),
+                    },
+                    Err(..) => Error::UnexpectedResponse(error, msg),
+                }
+            }
+            error => error,
+        })
+    }
+
+    pub(crate) fn parse_status_update(&self, body: &[u8]) -> Result<Update, Error> {
+        let update = Update::try_from(Fields::parse(body)).map_err(Error::MalformedUpdate)?;
+        update.validate_with(self)?;
+        Ok(update)
+    }
+
+    pub(crate) fn sign(&self, fields: &[(&str, &str)]) -> String {
+        let Hash(hash) = self
+            .hash(format_args!("{}", Values(fields)))
+            .expose_secret()
+            .clone();
+        hash
+    }
+
+    pub(crate) fn verify(&self, fields: &[(&str, &str)]) -> Result<KeyMatch, Error> {
+        let hash = fields
+            .iter()
+            .find(|(name, _)| *name == HASH)
+            .map(|(_, hash)| Secret::new(Hash((*hash).to_owned())))
+            .ok_or(Error::MissingHash)?;
+        self.validate_hash(&hash, format_args!("{}", Values(fields)))
+    }
+
+    pub(crate) fn hash(&self, msg: Arguments) -> Secret<Hash> {
+        Secret::new(Hash(format!("{:X}", Self::digest(&self.key, msg))))
+    }
+
+    pub(crate) fn validate_hash(
+        &self,
+        hash: &Secret<Hash>,
+        msg: Arguments,
+    ) -> Result<KeyMatch, Error> {
+        // Paynow's hashes are hex encoded so we decode them to compare the
+        // raw bytes, this makes the comparison case insensitive
+        if let Ok(received) = hex::decode(&hash.expose_secret().0) {
+            let matches = |key: &ApiKey| {
+                let expected = Self::digest(key, msg);
+                bool::from(received.as_slice().ct_eq(expected.as_slice()))
+            };
+            if matches(&self.key) {
+                return Ok(KeyMatch::Primary);
+            }
+            if self.accepts_previous_keys() {
+                if let Some(index) = self.previous_keys.iter().position(matches) {
+                    return Ok(KeyMatch::Previous(index));
                 }
-                error => error,
-            })?;
-        status.validate(self)?;
-        Ok(status)
+            }
+        }
+        Err(Error::HashMismatch(msg.to_string()))
     }
 
-    fn hash(&self, msg: Arguments) -> Secret<Hash> {
+    fn accepts_previous_keys(&self) -> bool {
+        match self.grace_period_end {
+            Some(end) => SystemTime::now() < end,
+            None => true,
+        }
+    }
+
+    fn digest(key: &ApiKey, msg: Arguments) -> Output<Sha512> {
         let mut hasher = Sha512::new();
         hasher.update(format!(
             "{msg}{key}",
             msg = msg,
-            key = self.key.expose_secret().0
+            key = key.expose_secret().0
         ));
-        Secret::new(Hash(format!("{:X}", hasher.finalize())))
+        hasher.finalize()
     }
 
-    fn validate_hash(&self, hash: &Secret<Hash>, msg: Arguments) -> Result<(), Error> {
-        let expected_hash = self.hash(msg);
-        if hash.expose_secret() != expected_hash.expose_secret() {
-            return Err(Error::HashMismatch(msg.to_string()));
+    /// Serialize a message and sign it
+    ///
+    /// The hash is computed from the serialized fields so it always covers
+    /// exactly what is sent to Paynow.
+    pub(crate) fn form<T: Serialize>(
+        &self,
+        endpoint: Url,
+        message: &T,
+    ) -> Result<transport::Request, Error> {
+        let mut fields = Fields::serialize(message).map_err(Error::SerializingRequest)?;
+        let hash = self.sign(&fields.pairs());
+        fields.push(HASH, hash);
+        Ok(transport::Request {
+            url: endpoint,
+            form: Some(fields.encode()),
+        })
+    }
+
+    /// Deserialize a response and verify its hash over the fields in the
+    /// order they were received
+    pub(crate) fn parse<O: DeserializeOwned>(
+        &self,
+        response: transport::Response,
+    ) -> Result<O, Error> {
+        if !response.is_success() {
+            return Err(Error::Response(response.status, response.body));
+        }
+        let message = response.body;
+        match serde_urlencoded::from_str(&message) {
+            Ok(output) => {
+                self.verify(&Fields::parse(message.as_bytes()).pairs())?;
+                Ok(output)
+            }
+            Err(error) => Err(Error::UnexpectedResponse(error, message)),
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+macro_rules! configure_reqwest {
+    ($config:expr, $builder:expr) => {{
+        let mut builder = $builder;
+        if let Some(timeout) = $config.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = $config.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        for proxy in &$config.proxies {
+            builder = builder.proxy(proxy.clone());
+        }
+        for cert in &$config.root_certificates {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        if let Some(user_agent) = &$config.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        builder
+    }};
+}
+
+const HASH: &str = "hash";
+
+/// Concatenated values of a message's fields, excluding its hash
+struct Values<'a>(&'a [(&'a str, &'a str)]);
+
+impl fmt::Display for Values<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (_, value) in self.0.iter().filter(|(name, _)| *name != HASH) {
+            f.write_str(value)?;
         }
         Ok(())
     }
+}
 
-    async fn submit<T, O>(&self, endpoint: Url, payload: Payload<'_, T>) -> Result<O, Error>
-    where
-        T: Serialize,
-        O: DeserializeOwned,
-    {
-        let mut request = self.req.post(endpoint);
-        request = match payload {
-            Payload::Form(payload) => request.form(payload),
-            Payload::Empty => request.header(CONTENT_LENGTH, 0),
+/// Paynow client builder
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    id: u64,
+    key: ApiKey,
+    previous_keys: Vec<ApiKey>,
+    grace_period: Option<Duration>,
+    base: Option<Url>,
+    retry: RetryPolicy,
+    environment: Option<Environment>,
+    merchant_email: Option<String>,
+    transport: Option<Arc<dyn Transport>>,
+    #[cfg(feature = "blocking")]
+    blocking_transport: Option<Arc<dyn blocking::Transport>>,
+    #[cfg(feature = "reqwest")]
+    timeout: Option<Duration>,
+    #[cfg(feature = "reqwest")]
+    connect_timeout: Option<Duration>,
+    #[cfg(feature = "reqwest")]
+    proxies: Vec<reqwest::Proxy>,
+    #[cfg(feature = "reqwest")]
+    root_certificates: Vec<reqwest::Certificate>,
+    #[cfg(feature = "reqwest")]
+    user_agent: Option<String>,
+}
+
+impl ClientBuilder {
+    /// Create new client builder
+    #[must_use]
+    pub fn new(id: u64, key: ApiKey) -> Self {
+        Self {
+            id,
+            key,
+            previous_keys: Vec::new(),
+            grace_period: None,
+            base: None,
+            retry: RetryPolicy::none(),
+            environment: None,
+            merchant_email: None,
+            transport: None,
+            #[cfg(feature = "blocking")]
+            blocking_transport: None,
+            #[cfg(feature = "reqwest")]
+            timeout: None,
+            #[cfg(feature = "reqwest")]
+            connect_timeout: None,
+            #[cfg(feature = "reqwest")]
+            proxies: Vec::new(),
+            #[cfg(feature = "reqwest")]
+            root_certificates: Vec::new(),
+            #[cfg(feature = "reqwest")]
+            user_agent: None,
+        }
+    }
+
+    /// Set the base URL that Paynow endpoints are resolved against
+    ///
+    /// Defaults to `https://www.paynow.co.zw/interface/`. The URL should end
+    /// with a `/` otherwise its last path segment will be replaced when
+    /// resolving endpoints.
+    pub fn base_url(&mut self, url: Url) -> &mut Self {
+        self.base = Some(url);
+        self
+    }
+
+    /// Add a previous integration key
+    ///
+    /// Hashes are always generated using the primary key passed to
+    /// [`ClientBuilder::new`], but messages signed with a previous key are
+    /// also accepted. This allows transactions that were initiated before a
+    /// key was rotated to complete. Previous keys are tried in the order they
+    /// were added.
+    pub fn previous_key(&mut self, key: ApiKey) -> &mut Self {
+        self.previous_keys.push(key);
+        self
+    }
+
+    /// Only accept messages signed with a previous key for this long after
+    /// the client is built
+    ///
+    /// By default previous keys are accepted for as long as the client lives.
+    pub fn grace_period(&mut self, period: Duration) -> &mut Self {
+        self.grace_period = Some(period);
+        self
+    }
+
+    /// Set the retry policy
+    ///
+    /// Defaults to [`RetryPolicy::none`].
+    pub fn retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Set the environment the integration is in
+    ///
+    /// When set, payments that break the rules of the environment are
+    /// rejected before they are sent. See [`test_mode`].
+    pub fn environment(&mut self, environment: Environment) -> &mut Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Set the email the merchant logs in to Paynow with
+    ///
+    /// In the test environment the auth email of payments has to be this
+    /// email, so it's required when the environment is
+    /// [`Environment::Test`].
+    pub fn merchant_email(&mut self, email: impl Into<String>) -> &mut Self {
+        self.merchant_email = Some(email.into());
+        self
+    }
+
+    /// Use a custom HTTP transport
+    ///
+    /// When set, the timeouts, proxies, root certificates and user agent
+    /// configured on this builder are ignored in favour of the settings of
+    /// the given transport.
+    pub fn transport(&mut self, transport: impl Transport + 'static) -> &mut Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Use a custom HTTP transport for blocking clients
+    ///
+    /// When set, the timeouts, proxies, root certificates and user agent
+    /// configured on this builder are ignored by
+    /// [`build_blocking`](Self::build_blocking).
+    #[cfg(feature = "blocking")]
+    pub fn blocking_transport(
+        &mut self,
+        transport: impl blocking::Transport + 'static,
+    ) -> &mut Self {
+        self.blocking_transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Use a pre-built HTTP client
+    ///
+    /// This is useful for sharing a connection pool. When set, the timeouts,
+    /// proxies, root certificates and user agent configured on this builder
+    /// are ignored in favour of the settings of the given client.
+    #[cfg(feature = "reqwest")]
+    pub fn http_client(&mut self, client: reqwest::Client) -> &mut Self {
+        self.transport(client)
+    }
+
+    /// Set a timeout for the whole request, from connecting until the
+    /// response body has been read
+    #[cfg(feature = "reqwest")]
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a timeout for the connect phase of a request
+    #[cfg(feature = "reqwest")]
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Add a proxy
+    #[cfg(feature = "reqwest")]
+    pub fn proxy(&mut self, proxy: reqwest::Proxy) -> &mut Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Add a trusted root certificate
+    #[cfg(feature = "reqwest")]
+    pub fn add_root_certificate(&mut self, cert: reqwest::Certificate) -> &mut Self {
+        self.root_certificates.push(cert);
+        self
+    }
+
+    /// Set the `User-Agent` header sent with every request
+    #[cfg(feature = "reqwest")]
+    pub fn user_agent(&mut self, user_agent: impl Into<String>) -> &mut Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Build the client
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the HTTP client cannot be initialised, for
+    /// example when the TLS backend fails to load, when no transport was
+    /// set and the `reqwest` feature is disabled, or when the environment is
+    /// [`Environment::Test`] and no merchant email was set
+    #[allow(clippy::missing_panics_doc)]
+    pub fn build(&self) -> Result<Client, Error> {
+        self.check_environment()?;
+        let transport = match &self.transport {
+            Some(transport) => transport.clone(),
+            None => self.default_transport()?,
         };
-        let response = request.send().await.map_err(Error::SendingRequest)?;
-        let code = response.status();
-        let message = response.text().await.map_err(Error::GettingText)?;
-        if !code.is_success() {
-            return Err(Error::Response(code, message));
+        Ok(Client {
+            transport,
+            config: self.config(),
+        })
+    }
+
+    /// Build a blocking client
+    ///
+    /// # Errors
+    ///
+    /// Returns an error when the HTTP client cannot be initialised, for
+    /// example when the TLS backend fails to load, when no blocking
+    /// transport was set and the `reqwest` feature is disabled, or when the
+    /// environment is [`Environment::Test`] and no merchant email was set
+    #[cfg(feature = "blocking")]
+    pub fn build_blocking(&self) -> Result<blocking::Client, Error> {
+        self.check_environment()?;
+        let transport = match &self.blocking_transport {
+            Some(transport) => transport.clone(),
+            None => self.default_blocking_transport()?,
+        };
+        Ok(blocking::Client {
+            transport,
+            config: self.config(),
+        })
+    }
+
+    /// Test mode payments can only be checked against the merchant email
+    fn check_environment(&self) -> Result<(), Error> {
+        match (self.environment, &self.merchant_email) {
+            (Some(Environment::Test), None) => Err(Error::MissingMerchantEmail),
+            _ => Ok(()),
+        }
+    }
+
+    fn config(&self) -> Config {
+        Config {
+            id: self.id,
+            key: self.key.clone(),
+            previous_keys: self.previous_keys.clone(),
+            grace_period_end: self
+                .grace_period
+                .and_then(|period| SystemTime::now().checked_add(period)),
+            base: match &self.base {
+                Some(base) => base.clone(),
+                // we know this is a valid URL so this should never panic
+                None => Url::parse(BASE_URL).unwrap(),
+            },
+            retry: self.retry.clone(),
+            environment: self.environment,
+            merchant_email: self.merchant_email.clone(),
         }
-        serde_urlencoded::from_str(&message).map_err(|e| Error::UnexpectedResponse(e, message))
     }
-}
 
-enum Payload<'a, T: Serialize> {
-    Empty,
-    Form(&'a T),
+    #[cfg(feature = "reqwest")]
+    fn default_transport(&self) -> Result<Arc<dyn Transport>, Error> {
+        let client = configure_reqwest!(self, reqwest::Client::builder())
+            .build()
+            .map_err(|e| Error::BuildingClient(Box::new(e)))?;
+        Ok(Arc::new(client))
+    }
+
+    #[cfg(not(feature = "reqwest"))]
+    #[allow(clippy::unused_self)]
+    fn default_transport(&self) -> Result<Arc<dyn Transport>, Error> {
+        Err(Error::MissingTransport)
+    }
+
+    #[cfg(all(feature = "blocking", feature = "reqwest"))]
+    fn default_blocking_transport(&self) -> Result<Arc<dyn blocking::Transport>, Error> {
+        let client = configure_reqwest!(self, reqwest::blocking::Client::builder())
+            .build()
+            .map_err(|e| Error::BuildingClient(Box::new(e)))?;
+        Ok(Arc::new(client))
+    }
+
+    #[cfg(all(feature = "blocking", not(feature = "reqwest")))]
+    #[allow(clippy::unused_self)]
+    fn default_blocking_transport(&self) -> Result<Arc<dyn blocking::Transport>, Error> {
+        Err(Error::MissingTransport)
+    }
 }
 
 /// Error returned by this crate
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
+    #[error("failed to build HTTP client")]
+    BuildingClient(#[source] BoxError),
+    #[error("no HTTP transport configured")]
+    MissingTransport,
+    #[error("failed to serialize request")]
+    SerializingRequest(#[source] serde_urlencoded::ser::Error),
     #[error("failed to send request to Paynow")]
-    SendingRequest(#[source] reqwest::Error),
+    SendingRequest(#[source] BoxError),
     #[error("failed to retrieve Paynow response text")]
-    GettingText(#[source] reqwest::Error),
+    GettingText(#[source] BoxError),
     #[error("got unexpected response from Paynow")]
     UnexpectedResponse(#[source] serde_urlencoded::de::Error, String),
 
@@ -293,12 +855,35 @@ pub enum Error {
     InvalidTracePaymentUrl(#[source] url::ParseError),
     #[error("insufficient balance")]
     InsufficientBalance,
+    #[error("malformed status update")]
+    MalformedUpdate(#[source] serde_urlencoded::de::Error),
     #[error("received invalid hash")]
     HashMismatch(String),
+    #[error("message has no hash")]
+    MissingHash,
+    #[error("payment was not paid and did not reach a final status in time")]
+    WatchTimeout,
+    #[error("status update is for a different transaction")]
+    UnrelatedUpdate(u64),
+    #[error("transaction not found")]
+    UnknownTransaction(u64),
+    #[error("test mode phone number used in the live environment")]
+    TestNumberInLive(String),
+    #[error("phone number is not a test mode number")]
+    LiveNumberInTest(String),
+    #[error("auth email must be the merchant's login email in test mode")]
+    TestAuthEmail(String),
+    #[error("merchant email is required in test mode")]
+    MissingMerchantEmail,
     #[error("merchant trace ID not found")]
     NotFound(String),
     #[error("Paynow returned an error")]
-    Response(reqwest::StatusCode, String),
+    Response(u16, String),
+    #[error("I/O error")]
+    Io(#[source] std::io::Error),
+    #[cfg(feature = "cassette")]
+    #[error("invalid cassette")]
+    Cassette(#[source] serde_json::Error),
     #[error("time format error")]
     TimeFormat(
         #[source]
@@ -306,3 +891,262 @@ pub enum Error {
         time::error::Format,
     ),
 }
+
+impl Error {
+    /// Whether the error is due to a missing or invalid hash
+    #[must_use]
+    pub fn is_signature_error(&self) -> bool {
+        matches!(self, Self::MissingHash | Self::HashMismatch(..))
+    }
+}
+
+impl From<transport::Error> for Error {
+    fn from(error: transport::Error) -> Self {
+        match error {
+            transport::Error::Send(error) => Self::SendingRequest(error),
+            transport::Error::Body(error) => Self::GettingText(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::key;
+
+    fn signed(config: &Config, fields: &[(&'static str, &'static str)]) -> Vec<(String, String)> {
+        let hash = config.sign(fields);
+        fields
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+            .chain([(HASH.to_owned(), hash)])
+            .collect()
+    }
+
+    fn verify(config: &Config, fields: &[(String, String)]) -> Result<KeyMatch, Error> {
+        let pairs: Vec<_> = fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        config.verify(&pairs)
+    }
+
+    fn with_hash(fields: &mut [(String, String)], hash: impl FnOnce(&str) -> String) {
+        let (_, value) = fields.iter_mut().find(|(name, _)| name == HASH).unwrap();
+        *value = hash(value);
+    }
+
+    const MESSAGE: [(&str, &str); 2] = [("status", "Ok"), ("reference", "1")];
+
+    #[test]
+    fn accepts_hashes_in_any_case() {
+        let config = ClientBuilder::new(1, key()).config();
+        let mut fields = signed(&config, &MESSAGE);
+        with_hash(&mut fields, str::to_lowercase);
+        assert_eq!(verify(&config, &fields).unwrap(), KeyMatch::Primary);
+        with_hash(&mut fields, |hash| {
+            hash.char_indices()
+                .map(|(i, c)| {
+                    if i % 2 == 0 {
+                        c.to_ascii_uppercase()
+                    } else {
+                        c
+                    }
+                })
+                .collect()
+        });
+        assert_eq!(verify(&config, &fields).unwrap(), KeyMatch::Primary);
+    }
+
+    #[test]
+    fn rejects_hashes_that_are_not_hex() {
+        let config = ClientBuilder::new(1, key()).config();
+        let mut fields = signed(&config, &MESSAGE);
+        with_hash(&mut fields, |hash| {
+            hash.replace(|c: char| c.is_ascii_digit(), "Z")
+        });
+        assert!(matches!(
+            verify(&config, &fields),
+            Err(Error::HashMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn accepts_previous_keys_during_grace_period() {
+        let previous = key();
+        let old = ClientBuilder::new(1, previous.clone()).config();
+        let fields = signed(&old, &MESSAGE);
+
+        let mut builder = ClientBuilder::new(1, key());
+        builder.previous_key(key()).previous_key(previous);
+        let config = builder.grace_period(Duration::from_secs(60)).config();
+        assert_eq!(verify(&config, &fields).unwrap(), KeyMatch::Previous(1));
+
+        let config = builder.grace_period(Duration::ZERO).config();
+        assert!(matches!(
+            verify(&config, &fields),
+            Err(Error::HashMismatch(..))
+        ));
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Message {
+        status: String,
+        reference: String,
+    }
+
+    fn response(fields: &[(String, String)]) -> transport::Response {
+        transport::Response {
+            status: 200,
+            body: serde_urlencoded::to_string(fields).unwrap(),
+        }
+    }
+
+    #[test]
+    fn verifies_fields_in_received_order() {
+        let config = ClientBuilder::new(1, key()).config();
+        let fields = signed(
+            &config,
+            &[("status", "Ok"), ("unknown", "new"), ("reference", "1")],
+        );
+        let message: Message = config.parse(response(&fields)).unwrap();
+        assert_eq!(message.status, "Ok");
+        assert_eq!(message.reference, "1");
+
+        // the same fields signed in a different order
+        let mut reordered = signed(
+            &config,
+            &[("status", "Ok"), ("reference", "1"), ("unknown", "new")],
+        );
+        reordered.swap(1, 2);
+        assert!(matches!(
+            config.parse::<Message>(response(&reordered)),
+            Err(Error::HashMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn requires_a_hash() {
+        let config = ClientBuilder::new(1, key()).config();
+        let mut fields = signed(&config, &MESSAGE);
+        fields.retain(|(name, _)| name != HASH);
+        assert!(matches!(verify(&config, &fields), Err(Error::MissingHash)));
+        assert!(matches!(
+            config.parse::<Message>(response(&fields)),
+            Err(Error::MissingHash)
+        ));
+    }
+
+    /// Configuration with a fixed key so hashes can be compared with known
+    /// answers
+    fn known_config() -> Config {
+        let key = Uuid::parse_str("3e9fed89-60e1-4ce5-ab6d-7c1a3e3c5c8f").unwrap();
+        ClientBuilder::new(1201, ApiKey::new(Key::from(key))).config()
+    }
+
+    fn result_url() -> Url {
+        Url::parse("https://example.com/paynow/result").unwrap()
+    }
+
+    #[test]
+    fn signs_payments() {
+        let config = known_config();
+        let result_url = result_url();
+        let return_url = Url::parse("https://example.com/checkout/return").unwrap();
+        let mut payment =
+            config.payment("Order 1", Decimal::new(1050, 2), &return_url, &result_url);
+        payment.additional_info("2 x Sadza & Beef");
+        let request = payment.request(&config).unwrap();
+        assert_eq!(
+            request.form.unwrap(),
+            concat!(
+                "id=1201&reference=Order+1&amount=10.50&additionalinfo=2+x+Sadza+%26+Beef",
+                "&returnurl=https%3A%2F%2Fexample.com%2Fcheckout%2Freturn",
+                "&resulturl=https%3A%2F%2Fexample.com%2Fpaynow%2Fresult&status=Message",
+                "&hash=52E4DBD9B316611783929B712BA09580C5D45EB0EB7971AE4C18ACB78B670C3E",
+                "0A5FC97E41A9E76C5F7C6AAA976FAB5DD1A888AE4ADC7C7B08615FBB7042AD39",
+            )
+        );
+    }
+
+    #[test]
+    fn signs_eco_cash_payments() {
+        let config = known_config();
+        let result_url = result_url();
+        let payment = config.express_payment(
+            express::Method::eco_cash("0771111111"),
+            "Order 2",
+            Decimal::new(500, 2),
+            &result_url,
+            "buyer@example.com",
+            "trace-2",
+        );
+        let request = payment.request(&config).unwrap();
+        assert_eq!(
+            request.form.unwrap(),
+            concat!(
+                "method=ecocash&id=1201&reference=Order+2&amount=5.00",
+                "&resulturl=https%3A%2F%2Fexample.com%2Fpaynow%2Fresult",
+                "&authemail=buyer%40example.com&merchanttrace=trace-2&status=Message",
+                "&phone=0771111111",
+                "&hash=1C7805262FE73FE912E2C314556F44065724DAF53815E4C2490C0BAA4448BFE3",
+                "E2CC642CA840A765533BCBFA8E25ECF3A5C812F8BAC86D801FF175471C557F2A",
+            )
+        );
+    }
+
+    #[test]
+    fn signs_card_payments() {
+        let config = known_config();
+        let result_url = result_url();
+        let country = celes::Country::zimbabwe();
+        let card = express::Card {
+            number: "4111111111111111",
+            name: "T Moyo",
+            cvv: "123",
+            expiry: "1229",
+        };
+        let address = express::Address {
+            line1: "1 Samora Machel Ave",
+            line2: None,
+            city: "Harare",
+            province: Some("Harare"),
+            country: &country,
+        };
+        let payment = config.express_payment(
+            express::Method::vmc(card, address, "tok-1"),
+            "Order 3",
+            Decimal::new(2500, 2),
+            &result_url,
+            "buyer@example.com",
+            "trace-3",
+        );
+        let request = payment.request(&config).unwrap();
+        assert_eq!(
+            request.form.unwrap(),
+            concat!(
+                "method=vmc&id=1201&reference=Order+3&amount=25.00",
+                "&resulturl=https%3A%2F%2Fexample.com%2Fpaynow%2Fresult",
+                "&authemail=buyer%40example.com&merchanttrace=trace-3&status=Message",
+                "&cardnumber=4111111111111111&cardname=T+Moyo&cardcvv=123&cardexpiry=1229",
+                "&billingline1=1+Samora+Machel+Ave&billingcity=Harare&billingprovince=Harare",
+                "&billingcountry=ZW&token=tok-1",
+                "&hash=6A22CCE5A1703011E825B40FD06FD1CA5EBD731EBFA1C4CE12411BFD167C3424",
+                "FDA6F321AACB4253C0EA29CA7FE410FC6F82E2226C9911E4134F370F25ECD4DA",
+            )
+        );
+    }
+
+    #[test]
+    fn signs_merchant_traces() {
+        let request = known_config().trace_request("trace-2").unwrap();
+        assert_eq!(
+            request.form.unwrap(),
+            concat!(
+                "id=1201&merchanttrace=trace-2&status=Message",
+                "&hash=EAA9AE681A30FDB0853663A5456C18CAABE24B69970447E2A8375A67711D21AA",
+                "7B8B9318E1CD6B04B8BAD68D6291D356206E11E6030D7AB69D8EB55E45A423BF",
+            )
+        );
+    }
+}
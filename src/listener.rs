@@ -0,0 +1,289 @@
+//! Standalone result URL listener
+//!
+//! For small deployments that don't already run a web server, [`bind`] starts
+//! a minimal HTTP server that receives the status updates Paynow posts to the
+//! result URL and yields them as a [`Stream`].
+
+use crate::status::Update;
+use crate::webhook::{self, reply, Rejected};
+use crate::{Client, Error};
+use futures_core::Stream;
+use http::{Method, Request, Response, StatusCode};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio::task::{JoinHandle, JoinSet};
+
+/// Number of updates buffered until the stream is consumed
+const CAPACITY: usize = 64;
+
+/// Delay before accepting connections again after accepting one failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Start listening for status updates posted to `path` on `addr`
+///
+/// `path` is the path of the result URL, for example `/paynow/result`. Updates
+/// are verified with `client` and Paynow is answered as soon as an update has
+/// been verified, before it is consumed from the stream. Updates that fail
+/// verification are answered with `400 Bad Request` or `401 Unauthorized` and
+/// yielded as errors.
+///
+/// Requests to other paths are answered with `404 Not Found` and bodies larger
+/// than 16 KiB with `413 Payload Too Large`. At most 64 updates are buffered,
+/// once the buffer is full updates are answered with
+/// `503 Service Unavailable` so that Paynow sends them again later.
+///
+/// The server runs on the current Tokio runtime until the returned
+/// [`Listener`] is dropped, which also closes the connections it has open.
+///
+/// # Errors
+///
+/// Returns an error if binding to `addr` fails
+pub async fn bind(
+    addr: impl ToSocketAddrs,
+    path: impl Into<String>,
+    client: Client,
+) -> Result<Listener, Error> {
+    let listener = TcpListener::bind(addr).await.map_err(Error::Io)?;
+    let local_addr = listener.local_addr().map_err(Error::Io)?;
+    let (sender, updates) = mpsc::channel(CAPACITY);
+    let server = tokio::spawn(serve(listener, Arc::from(path.into()), client, sender));
+    Ok(Listener {
+        local_addr,
+        updates,
+        server,
+    })
+}
+
+/// Stream of status updates received by the listener started with [`bind`]
+#[derive(Debug)]
+pub struct Listener {
+    local_addr: SocketAddr,
+    updates: Receiver<Result<Update, Error>>,
+    server: JoinHandle<()>,
+}
+
+impl Listener {
+    /// Get the address the listener is bound to
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Stream for Listener {
+    type Item = Result<Update, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.updates.poll_recv(cx)
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    path: Arc<str>,
+    client: Client,
+    sender: Sender<Result<Update, Error>>,
+) {
+    // dropped along with the task when the listener is, aborting every
+    // connection still open
+    let mut connections = JoinSet::new();
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(error) => {
+                if sender.send(Err(Error::Io(error))).await.is_err() {
+                    return;
+                }
+                // accepting keeps failing while the process is out of file
+                // descriptors, which would flood the stream with errors
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let path = path.clone();
+        let client = client.clone();
+        let sender = sender.clone();
+        let service =
+            service_fn(move |req| receive(req, path.clone(), client.clone(), sender.clone()));
+        while connections.try_join_next().is_some() {}
+        connections.spawn(async move {
+            // connection errors are between Paynow and the server, there is
+            // nothing the consumer of the stream can do about them
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+async fn receive(
+    req: Request<Incoming>,
+    path: Arc<str>,
+    client: Client,
+    sender: Sender<Result<Update, Error>>,
+) -> Result<Response<String>, Infallible> {
+    if req.uri().path() != &*path {
+        return Ok(reply(StatusCode::NOT_FOUND));
+    }
+    if req.method() != Method::POST {
+        return Ok(reply(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let (status, update) = match webhook::read_update(&client, req.into_body()).await {
+        Ok(update) => (StatusCode::OK, Ok(update)),
+        // only updates that fail verification are yielded to the stream
+        Err(rejected) => match (rejected.status(), rejected) {
+            (status, Rejected::Update(error)) => (status, Err(error)),
+            (status, Rejected::Body(..)) => return Ok(reply(status)),
+        },
+    };
+    match sender.try_send(update) {
+        Ok(()) => Ok(reply(status)),
+        // once the stream has been dropped nobody would see the update, so
+        // Paynow is asked to send it again
+        Err(TrySendError::Full(..) | TrySendError::Closed(..)) => {
+            Ok(reply(StatusCode::SERVICE_UNAVAILABLE))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, offline_client, update_body};
+    use crate::webhook::MAX_BODY;
+    use std::io::{Read, Write};
+
+    async fn post(addr: SocketAddr, path: &'static str, body: String) -> String {
+        tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Type: application/x-www-form-urlencoded\r\n\
+                 Content-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+            let mut response = String::new();
+            // the server may close the connection before reading all of an
+            // oversized body, which can cut the response short
+            let _ = stream.read_to_string(&mut response);
+            response
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn listener() -> Listener {
+        bind("127.0.0.1:0", "/result", offline_client(key()))
+            .await
+            .unwrap()
+    }
+
+    fn request(body: &str) -> String {
+        format!(
+            "POST /result HTTP/1.1\r\nHost: localhost\r\n\
+             Content-Type: application/x-www-form-urlencoded\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    /// Read the status line and headers of a response with an empty body
+    fn response(stream: &mut std::net::TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0];
+        while !response.ends_with(b"\r\n\r\n") {
+            match stream.read(&mut byte) {
+                Ok(1) => response.push(byte[0]),
+                _ => break,
+            }
+        }
+        String::from_utf8(response).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_other_paths() {
+        let listener = listener().await;
+        let response = post(listener.local_addr(), "/other", String::new()).await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_bodies() {
+        let listener = listener().await;
+        let response = post(listener.local_addr(), "/result", "a".repeat(MAX_BODY + 1)).await;
+        assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn yields_rejected_updates() {
+        let mut listener = listener().await;
+        let response = post(listener.local_addr(), "/result", "status=Paid".to_owned()).await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+        let update = futures_util::StreamExt::next(&mut listener).await.unwrap();
+        assert!(update.is_err());
+    }
+
+    #[tokio::test]
+    async fn yields_verified_updates() {
+        let client = offline_client(key());
+        let body = update_body(&client);
+        let mut listener = bind("127.0.0.1:0", "/result", client).await.unwrap();
+        let response = post(listener.local_addr(), "/result", body).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        let update = futures_util::StreamExt::next(&mut listener)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.reference(), "Order 1");
+        assert_eq!(update.paynow_reference(), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_accepting_updates_once_dropped() {
+        let client = offline_client(key());
+        let body = update_body(&client);
+        let listener = bind("127.0.0.1:0", "/result", client).await.unwrap();
+        let mut stream = std::net::TcpStream::connect(listener.local_addr()).unwrap();
+        let (mut stream, first) = tokio::task::spawn_blocking({
+            let body = body.clone();
+            move || {
+                write!(stream, "{}", request(&body)).unwrap();
+                let first = response(&mut stream);
+                (stream, first)
+            }
+        })
+        .await
+        .unwrap();
+        assert!(first.starts_with("HTTP/1.1 200"), "{first}");
+        drop(listener);
+        let second = tokio::task::spawn_blocking(move || {
+            // the connection may already be closed
+            let _ = write!(stream, "{}", request(&body));
+            response(&mut stream)
+        })
+        .await
+        .unwrap();
+        assert!(
+            second.is_empty() || second.starts_with("HTTP/1.1 503"),
+            "{second}"
+        );
+    }
+}
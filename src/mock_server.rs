@@ -0,0 +1,855 @@
+//! Local Paynow stand-in for offline testing
+//!
+//! [`start`] runs an HTTP server that implements the endpoints the
+//! [`Client`](crate::Client) talks to. Messages are verified and signed with
+//! the integration key the server was started with, exactly like Paynow does,
+//! so a client built with [`MockServer::client_builder`] works against it
+//! unchanged.
+//!
+//! Tests drive transactions through their statuses with
+//! [`MockServer::set_status`] and deliver the resulting status updates to the
+//! result URL with [`MockServer::notify`]. How the client copes with Paynow
+//! misbehaving is tested by injecting [`Fault`]s with [`MockServer::inject`].
+
+use crate::form::Fields;
+use crate::status::{Status, Update};
+use crate::transport::BoxError;
+use crate::{ApiKey, ClientBuilder, Config, Error};
+use http::header::{HeaderValue, CONTENT_TYPE, HOST};
+use http::{Method, Request, Response, StatusCode};
+use http_body_util::BodyExt;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use rust_decimal::Decimal;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// Paynow reference of the first transaction initiated on a server
+const FIRST_REFERENCE: u64 = 1_000_001;
+
+/// Delay before accepting connections again after accepting one failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Start a mock Paynow server on a random local port
+///
+/// Only messages for integration `id`, signed with `key`, are accepted. The
+/// server runs on the current Tokio runtime until the returned
+/// [`MockServer`] is dropped.
+///
+/// # Errors
+///
+/// Returns an error if binding to a local port fails
+pub async fn start(id: u64, key: ApiKey) -> Result<MockServer, Error> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .map_err(Error::Io)?;
+    let local_addr = listener.local_addr().map_err(Error::Io)?;
+    let state = Arc::new(State {
+        config: ClientBuilder::new(id, key).config(),
+        // a socket address always makes a valid URL so this should never panic
+        base: Url::parse(&format!("http://{local_addr}/interface/")).unwrap(),
+        transactions: Mutex::new(Vec::new()),
+        faults: Mutex::new(Vec::new()),
+    });
+    let server = tokio::spawn(serve(listener, state.clone()));
+    Ok(MockServer {
+        local_addr,
+        state,
+        server,
+    })
+}
+
+/// Mock Paynow server started with [`start`]
+#[derive(Debug)]
+pub struct MockServer {
+    local_addr: SocketAddr,
+    state: Arc<State>,
+    server: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Get the address the server is bound to
+    #[must_use]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Get a reference to the base URL Paynow endpoints are served under
+    #[must_use]
+    pub fn base_url(&self) -> &Url {
+        &self.state.base
+    }
+
+    /// Create a builder for a client that talks to this server
+    #[must_use]
+    pub fn client_builder(&self) -> ClientBuilder {
+        let config = &self.state.config;
+        let mut builder = ClientBuilder::new(config.id, config.key.clone());
+        builder.base_url(self.state.base.clone());
+        builder
+    }
+
+    /// Get the transactions initiated on this server so far
+    #[must_use]
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.state.transactions().clone()
+    }
+
+    /// Get a transaction by its Paynow reference
+    #[must_use]
+    pub fn transaction(&self, paynow_reference: u64) -> Option<Transaction> {
+        self.state
+            .transactions()
+            .iter()
+            .find(|transaction| transaction.paynow_reference == paynow_reference)
+            .cloned()
+    }
+
+    /// Change the status of a transaction, returning the status update that
+    /// polling it now returns
+    ///
+    /// Any status can be set, whether Paynow would allow the transition or
+    /// not. Returns `None` if there is no transaction with this reference.
+    pub fn set_status(&self, paynow_reference: u64, status: Status) -> Option<Update> {
+        let mut transactions = self.state.transactions();
+        let transaction = transactions
+            .iter_mut()
+            .find(|transaction| transaction.paynow_reference == paynow_reference)?;
+        transaction.status = status;
+        Update::try_from(self.state.update(transaction)).ok()
+    }
+
+    /// Inject a fault into the response to the next request to `endpoint`
+    ///
+    /// Faults are queued, each one is used for a single request in the order
+    /// they were injected.
+    pub fn inject(&self, endpoint: Endpoint, fault: Fault) {
+        self.state.faults().push((endpoint, fault));
+    }
+
+    /// Remove all faults that haven't been used yet
+    pub fn clear_faults(&self) {
+        self.state.faults().clear();
+    }
+
+    /// Post the current status of a transaction to its result URL
+    ///
+    /// Only `http` result URLs are supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is no transaction with this reference, the
+    /// update can't be delivered or the result URL doesn't respond with a
+    /// `2xx` status
+    pub async fn notify(&self, paynow_reference: u64) -> Result<(), Error> {
+        let (result_url, update) = {
+            let transactions = self.state.transactions();
+            let transaction = transactions
+                .iter()
+                .find(|transaction| transaction.paynow_reference == paynow_reference)
+                .ok_or(Error::UnknownTransaction(paynow_reference))?;
+            (
+                transaction.result_url.clone(),
+                self.state.update(transaction),
+            )
+        };
+        post(&result_url, update.encode()).await
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Transaction initiated on a [`MockServer`]
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    reference: String,
+    paynow_reference: u64,
+    amount: Decimal,
+    status: Status,
+    poll_url: Url,
+    result_url: Url,
+    express: bool,
+    request: Fields,
+}
+
+impl Transaction {
+    /// Get a reference to the merchant's reference
+    #[must_use]
+    pub fn reference(&self) -> &str {
+        &self.reference
+    }
+
+    /// Get Paynow reference
+    #[must_use]
+    pub fn paynow_reference(&self) -> u64 {
+        self.paynow_reference
+    }
+
+    /// Get amount
+    #[must_use]
+    pub fn amount(&self) -> Decimal {
+        self.amount
+    }
+
+    /// Get a reference to the current status
+    #[must_use]
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Get a reference to the poll URL
+    #[must_use]
+    pub fn poll_url(&self) -> &Url {
+        &self.poll_url
+    }
+
+    /// Get a reference to the result URL
+    #[must_use]
+    pub fn result_url(&self) -> &Url {
+        &self.result_url
+    }
+
+    /// Whether the transaction was initiated as an express payment
+    #[must_use]
+    pub fn is_express(&self) -> bool {
+        self.express
+    }
+
+    /// Get the value of a field of the initiation request, for example
+    /// `phone` or `merchanttrace`
+    #[must_use]
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.request.get(name)
+    }
+}
+
+/// Endpoint of a [`MockServer`] a [`Fault`] applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    /// Every endpoint
+    Any,
+    /// `initiatetransaction`, used to initiate payments
+    InitiateTransaction,
+    /// `remotetransaction`, used to initiate express payments
+    RemoteTransaction,
+    /// `trace`, used to look up payments by merchant trace
+    Trace,
+    /// Poll URLs
+    Poll,
+}
+
+impl Endpoint {
+    fn matches(self, endpoint: Endpoint) -> bool {
+        self == Endpoint::Any || self == endpoint
+    }
+}
+
+/// Fault injected into a response of a [`MockServer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fault {
+    /// Process the request but respond with an invalid hash
+    BadHash,
+    /// Respond with `status=Error` and this error message, without processing
+    /// the request
+    Error(String),
+    /// Respond with an HTML error page with this HTTP status code, without
+    /// processing the request
+    Html(u16),
+    /// Wait this long before processing the request and responding
+    Delay(Duration),
+    /// Process the request but close the connection without responding, as
+    /// if the response was lost on the way back
+    Disconnect,
+}
+
+impl Fault {
+    /// Paynow's error for an unknown integration ID
+    #[must_use]
+    pub fn invalid_id() -> Self {
+        Fault::Error("Invalid Id.".to_owned())
+    }
+
+    /// Paynow's error for an amount it doesn't accept
+    #[must_use]
+    pub fn invalid_amount() -> Self {
+        Fault::Error("Invalid amount field.".to_owned())
+    }
+
+    /// Paynow's error for an amount that is too large
+    #[must_use]
+    pub fn amount_overflow() -> Self {
+        Fault::Error("Conversion overflows.".to_owned())
+    }
+
+    /// Paynow's error for a customer who can't afford the payment
+    #[must_use]
+    pub fn insufficient_balance() -> Self {
+        Fault::Error("Insufficient balance".to_owned())
+    }
+}
+
+/// Endpoint a request was sent to
+enum Route {
+    Initiate,
+    Remote,
+    Trace,
+    Poll(u64),
+}
+
+impl Route {
+    fn parse(path: &str) -> Option<Self> {
+        match path {
+            "initiatetransaction" => Some(Route::Initiate),
+            "remotetransaction" => Some(Route::Remote),
+            "trace" => Some(Route::Trace),
+            _ => path
+                .strip_prefix("poll/")
+                .and_then(|reference| reference.parse().ok())
+                .map(Route::Poll),
+        }
+    }
+
+    fn endpoint(&self) -> Endpoint {
+        match self {
+            Route::Initiate => Endpoint::InitiateTransaction,
+            Route::Remote => Endpoint::RemoteTransaction,
+            Route::Trace => Endpoint::Trace,
+            Route::Poll(..) => Endpoint::Poll,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    config: Config,
+    base: Url,
+    transactions: Mutex<Vec<Transaction>>,
+    faults: Mutex<Vec<(Endpoint, Fault)>>,
+}
+
+impl State {
+    fn transactions(&self) -> MutexGuard<'_, Vec<Transaction>> {
+        // transactions are always left in a consistent state
+        self.transactions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Vec<(Endpoint, Fault)>> {
+        self.faults.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn take_fault(&self, endpoint: Endpoint) -> Option<Fault> {
+        let mut faults = self.faults();
+        let index = faults
+            .iter()
+            .position(|(target, _)| target.matches(endpoint))?;
+        Some(faults.remove(index).1)
+    }
+
+    fn initiate(&self, request: Fields, express: bool) -> Fields {
+        if let Some(error) = self.authenticate(&request) {
+            return error;
+        }
+        let amount = match request.get("amount").map(str::parse::<Decimal>) {
+            Some(Ok(amount)) if amount > Decimal::ZERO => amount,
+            _ => return error("Invalid amount field."),
+        };
+        let reference = match request.get("reference") {
+            Some(reference) => reference.to_owned(),
+            None => return error("Missing reference."),
+        };
+        let result_url = match request.get("resulturl").map(Url::parse) {
+            Some(Ok(url)) => url,
+            _ => return error("Invalid resulturl."),
+        };
+        let mut transactions = self.transactions();
+        let paynow_reference = FIRST_REFERENCE + transactions.len() as u64;
+        let poll_url = self.url(&format!("poll/{paynow_reference}"));
+        let mut response = Fields::default();
+        response.push("status", "Ok".to_owned());
+        if express {
+            response.push(
+                "instructions",
+                "Dial *151*2*7# and enter your PIN to approve the payment".to_owned(),
+            );
+            response.push("paynowreference", paynow_reference.to_string());
+        } else {
+            let browser_url = self.url(&format!("payment/{paynow_reference}"));
+            response.push("browserurl", browser_url.to_string());
+        }
+        response.push("pollurl", poll_url.to_string());
+        transactions.push(Transaction {
+            reference,
+            paynow_reference,
+            amount,
+            status: if express {
+                Status::Sent
+            } else {
+                Status::Created
+            },
+            poll_url,
+            result_url,
+            express,
+            request,
+        });
+        self.signed(response)
+    }
+
+    fn trace(&self, request: &Fields) -> Fields {
+        if let Some(error) = self.authenticate(request) {
+            return error;
+        }
+        let transactions = self.transactions();
+        let transaction = transactions.iter().find(|transaction| {
+            transaction.request.get("merchanttrace").is_some()
+                && transaction.request.get("merchanttrace") == request.get("merchanttrace")
+        });
+        match transaction {
+            Some(transaction) => self.update(transaction),
+            None => {
+                let mut response = Fields::default();
+                response.push("status", "NotFound".to_owned());
+                self.signed(response)
+            }
+        }
+    }
+
+    fn poll(&self, paynow_reference: u64) -> Fields {
+        let transactions = self.transactions();
+        match transactions
+            .iter()
+            .find(|transaction| transaction.paynow_reference == paynow_reference)
+        {
+            Some(transaction) => self.update(transaction),
+            None => error("Invalid Id."),
+        }
+    }
+
+    /// Check the integration ID and hash of a request, returning the error
+    /// Paynow would respond with
+    fn authenticate(&self, request: &Fields) -> Option<Fields> {
+        if request.get("id") != Some(self.config.id.to_string().as_str()) {
+            return Some(error("Invalid Id."));
+        }
+        if self.config.verify(&request.pairs()).is_err() {
+            return Some(error("Hashes do not match."));
+        }
+        None
+    }
+
+    fn update(&self, transaction: &Transaction) -> Fields {
+        let mut update = Fields::default();
+        update.push("reference", transaction.reference.clone());
+        update.push("paynowreference", transaction.paynow_reference.to_string());
+        update.push("amount", transaction.amount.to_string());
+        update.push("status", transaction.status.to_string());
+        update.push("pollurl", transaction.poll_url.to_string());
+        self.signed(update)
+    }
+
+    fn signed(&self, mut fields: Fields) -> Fields {
+        let hash = self.config.sign(&fields.pairs());
+        fields.push(crate::HASH, hash);
+        fields
+    }
+
+    fn url(&self, path: &str) -> Url {
+        // paths are always relative so this should never panic
+        self.base.join(path).unwrap()
+    }
+}
+
+/// Paynow error message, which is not signed
+fn error(message: &str) -> Fields {
+    let mut fields = Fields::default();
+    fields.push("status", "Error".to_owned());
+    fields.push("error", message.to_owned());
+    fields
+}
+
+/// Replace the hash of a message with one no key produces
+fn tamper(message: &Fields) -> Fields {
+    let mut fields = Fields::default();
+    for (name, value) in message.pairs() {
+        if name != crate::HASH {
+            fields.push(name, value.to_owned());
+        }
+    }
+    fields.push(crate::HASH, "0".repeat(128));
+    fields
+}
+
+async fn serve(listener: TcpListener, state: Arc<State>) {
+    loop {
+        // accepting fails when the process is out of file descriptors or the
+        // client hung up already, either way the next one may succeed once
+        // some have been freed up
+        let Ok((stream, _)) = listener.accept().await else {
+            tokio::time::sleep(ACCEPT_BACKOFF).await;
+            continue;
+        };
+        let state = state.clone();
+        let service = service_fn(move |req| handle(req, state.clone()));
+        tokio::spawn(async move {
+            // connection errors are for the client under test to deal with
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
+
+/// Handle a request, failing when the connection has to be closed without
+/// responding
+async fn handle(req: Request<Incoming>, state: Arc<State>) -> Result<Response<String>, BoxError> {
+    if req.method() != Method::POST {
+        return Ok(reply(StatusCode::METHOD_NOT_ALLOWED, String::new()));
+    }
+    let route = req
+        .uri()
+        .path()
+        .strip_prefix(state.base.path())
+        .and_then(Route::parse);
+    let Some(route) = route else {
+        return Ok(reply(StatusCode::NOT_FOUND, String::new()));
+    };
+    let body = match req.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(..) => return Ok(reply(StatusCode::BAD_REQUEST, String::new())),
+    };
+    let fault = state.take_fault(route.endpoint());
+    match &fault {
+        Some(Fault::Error(message)) => return Ok(reply(StatusCode::OK, error(message).encode())),
+        Some(Fault::Html(status)) => return Ok(html(*status)),
+        Some(Fault::Delay(delay)) => tokio::time::sleep(*delay).await,
+        _ => {}
+    }
+    let request = Fields::parse(&body);
+    let response = match route {
+        Route::Initiate => state.initiate(request, false),
+        Route::Remote => state.initiate(request, true),
+        Route::Trace => state.trace(&request),
+        Route::Poll(paynow_reference) => state.poll(paynow_reference),
+    };
+    match fault {
+        Some(Fault::BadHash) => Ok(reply(StatusCode::OK, tamper(&response).encode())),
+        Some(Fault::Disconnect) => Err("response dropped by injected fault".into()),
+        _ => Ok(reply(StatusCode::OK, response.encode())),
+    }
+}
+
+fn reply(status: StatusCode, body: String) -> Response<String> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    response
+}
+
+/// Error page like the ones served by the web server in front of Paynow
+fn html(status: u16) -> Response<String> {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let title = format!(
+        "{} {}",
+        status.as_str(),
+        status.canonical_reason().unwrap_or_default()
+    );
+    let mut response = reply(
+        status,
+        format!("<html><head><title>{title}</title></head><body><h1>{title}</h1></body></html>"),
+    );
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    response
+}
+
+/// Post a form to a plain HTTP URL
+async fn post(url: &Url, form: String) -> Result<(), Error> {
+    let send = |error: BoxError| Error::SendingRequest(error);
+    if url.scheme() != "http" {
+        return Err(send("only http result URLs are supported".into()));
+    }
+    let host = url.host_str().unwrap_or_default();
+    let port = url.port_or_known_default().unwrap_or(80);
+    let stream = TcpStream::connect((host, port))
+        .await
+        .map_err(|e| send(Box::new(e)))?;
+    let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(|e| send(Box::new(e)))?;
+    // the connection is closed once the response has been read
+    tokio::spawn(connection);
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_owned(),
+    };
+    let request = Request::post(path)
+        .header(
+            HOST,
+            &url[url::Position::BeforeHost..url::Position::AfterPort],
+        )
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(form)
+        .map_err(|e| send(Box::new(e)))?;
+    let response = sender
+        .send_request(request)
+        .await
+        .map_err(|e| send(Box::new(e)))?;
+    let status = response.status();
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(|e| Error::GettingText(Box::new(e)))?
+        .to_bytes();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(Error::Response(
+            status.as_u16(),
+            String::from_utf8_lossy(&body).into_owned(),
+        ))
+    }
+}
+
+#[cfg(all(test, feature = "reqwest"))]
+mod tests {
+    use super::*;
+    use crate::payment::express::{Method, Outcome};
+    use crate::payment::Submit;
+    use crate::retry::RetryPolicy;
+    use crate::test_util::key;
+    use crate::Client;
+    use hyper::body::Bytes;
+    use std::time::Instant;
+    use tokio::sync::mpsc;
+
+    const TRACE: &str = "trace-1";
+
+    async fn server() -> (MockServer, Client) {
+        let server = start(1, key()).await.unwrap();
+        // retrying would hide the faults injected by the tests
+        let client = server
+            .client_builder()
+            .retry_policy(RetryPolicy::none())
+            .build()
+            .unwrap();
+        (server, client)
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    async fn initiate(client: &Client, result_url: &Url) -> Result<Update, Error> {
+        let return_url = url("http://localhost/return");
+        let payment = client.payment("Order 1", Decimal::TEN, &return_url, result_url);
+        let response = payment.submit(client).await?;
+        client.poll_status(response.take_poll_url()).await
+    }
+
+    async fn express(client: &Client) -> Result<u64, Error> {
+        let result_url = url("http://localhost/result");
+        let payment = client.express_payment(
+            Method::eco_cash("0771111111"),
+            "Order 1",
+            Decimal::TEN,
+            &result_url,
+            "customer@example.com",
+            TRACE,
+        );
+        Ok(payment.submit(client).await?.paynow_reference())
+    }
+
+    /// Start a result URL that hands the bodies posted to it to the receiver
+    async fn result_url() -> (Url, mpsc::Receiver<Bytes>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let url = url(&format!("http://{}/result", listener.local_addr().unwrap()));
+        let (sender, bodies) = mpsc::channel(1);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let sender = sender.clone();
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let sender = sender.clone();
+                    async move {
+                        let body = req.into_body().collect().await?.to_bytes();
+                        let _ = sender.send(body).await;
+                        Ok::<_, hyper::Error>(Response::new(String::new()))
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        (url, bodies)
+    }
+
+    #[tokio::test]
+    async fn initiates_and_polls_payments() {
+        let (server, client) = server().await;
+        let result_url = url("http://localhost/result");
+        let update = initiate(&client, &result_url).await.unwrap();
+        assert_eq!(update.status(), &Status::Created);
+        assert_eq!(update.paynow_reference(), FIRST_REFERENCE);
+        let transaction = server.transaction(FIRST_REFERENCE).unwrap();
+        assert_eq!(transaction.reference(), "Order 1");
+        assert_eq!(transaction.amount(), Decimal::TEN);
+        assert_eq!(transaction.result_url(), &result_url);
+        assert!(!transaction.is_express());
+    }
+
+    #[tokio::test]
+    async fn sets_statuses() {
+        let (server, client) = server().await;
+        let paynow_reference = express(&client).await.unwrap();
+        let transaction = server.transaction(paynow_reference).unwrap();
+        assert!(transaction.is_express());
+        assert_eq!(transaction.field("phone"), Some("0771111111"));
+        let update = server.set_status(paynow_reference, Status::Paid).unwrap();
+        assert_eq!(update.status(), &Status::Paid);
+        assert!(update.validate(&client).is_ok());
+        let update = client
+            .poll_status(transaction.poll_url().clone())
+            .await
+            .unwrap();
+        assert_eq!(update.status(), &Status::Paid);
+        assert!(server.set_status(0, Status::Paid).is_none());
+    }
+
+    #[tokio::test]
+    async fn traces_payments() {
+        let (server, client) = server().await;
+        let paynow_reference = express(&client).await.unwrap();
+        server.set_status(paynow_reference, Status::Paid);
+        let update = client.trace_payment(TRACE).await.unwrap();
+        assert_eq!(update.paynow_reference(), paynow_reference);
+        assert_eq!(update.status(), &Status::Paid);
+    }
+
+    #[tokio::test]
+    async fn reports_unknown_traces() {
+        let (_server, client) = server().await;
+        assert!(matches!(
+            client.trace_payment(TRACE).await,
+            Err(Error::NotFound(trace)) if trace == TRACE
+        ));
+    }
+
+    #[tokio::test]
+    async fn notifies_result_url() {
+        let (server, client) = server().await;
+        let (result_url, mut bodies) = result_url().await;
+        initiate(&client, &result_url).await.unwrap();
+        server.set_status(FIRST_REFERENCE, Status::Paid);
+        server.notify(FIRST_REFERENCE).await.unwrap();
+        let body = bodies.recv().await.unwrap();
+        let update = client.parse_status_update(&body).unwrap();
+        assert_eq!(update.paynow_reference(), FIRST_REFERENCE);
+        assert_eq!(update.status(), &Status::Paid);
+        assert!(matches!(
+            server.notify(0).await,
+            Err(Error::UnknownTransaction(0))
+        ));
+    }
+
+    #[tokio::test]
+    async fn injects_bad_hashes() {
+        let (server, client) = server().await;
+        server.inject(Endpoint::Poll, Fault::BadHash);
+        let result_url = url("http://localhost/result");
+        let result = initiate(&client, &result_url).await;
+        assert!(matches!(result, Err(Error::HashMismatch(..))), "{result:?}");
+    }
+
+    #[tokio::test]
+    async fn injects_errors() {
+        let (server, client) = server().await;
+        server.inject(Endpoint::InitiateTransaction, Fault::invalid_id());
+        let result_url = url("http://localhost/result");
+        let result = initiate(&client, &result_url).await;
+        assert!(matches!(result, Err(Error::InvalidId(1))), "{result:?}");
+        assert!(server.transactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn injects_error_pages() {
+        let (server, client) = server().await;
+        server.inject(Endpoint::Any, Fault::Html(502));
+        let result = express(&client).await;
+        assert!(matches!(result, Err(Error::Response(502, _))), "{result:?}");
+        assert!(server.transactions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn injects_delays() {
+        let (server, client) = server().await;
+        let delay = Duration::from_millis(50);
+        server.inject(Endpoint::RemoteTransaction, Fault::Delay(delay));
+        let start = Instant::now();
+        express(&client).await.unwrap();
+        assert!(start.elapsed() >= delay);
+    }
+
+    #[tokio::test]
+    async fn injects_disconnects() {
+        let (server, client) = server().await;
+        server.inject(Endpoint::RemoteTransaction, Fault::Disconnect);
+        let result = express(&client).await;
+        assert!(
+            matches!(result, Err(Error::SendingRequest(..))),
+            "{result:?}"
+        );
+        // the payment was initiated even though the response was lost
+        assert_eq!(server.transactions().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn clears_faults() {
+        let (server, client) = server().await;
+        server.inject(Endpoint::Any, Fault::Disconnect);
+        server.clear_faults();
+        express(&client).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn recovers_lost_express_payments() {
+        let server = start(1, key()).await.unwrap();
+        let mut policy = RetryPolicy::default();
+        policy
+            .retry_initiation(true)
+            .initial_backoff(Duration::from_millis(1));
+        let client = server
+            .client_builder()
+            .retry_policy(policy)
+            .build()
+            .unwrap();
+        server.inject(Endpoint::RemoteTransaction, Fault::Disconnect);
+        let result_url = url("http://localhost/result");
+        let payment = client.express_payment(
+            Method::eco_cash("0771111111"),
+            "Order 1",
+            Decimal::TEN,
+            &result_url,
+            "customer@example.com",
+            TRACE,
+        );
+        let outcome = payment.submit_or_recover(&client).await.unwrap();
+        assert!(
+            matches!(&outcome, Outcome::Recovered(update) if update.paynow_reference() == FIRST_REFERENCE),
+            "{outcome:?}"
+        );
+        // the payment wasn't sent again even though initiation is retried
+        assert_eq!(server.transactions().len(), 1);
+    }
+}
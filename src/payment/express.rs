@@ -1,32 +1,11 @@
 //! Express payment messages
 
-macro_rules! concat_express_payment {
-    ($name:expr, $payment:expr, $method_args:expr) => {
-        format_args!(
-            "{name}{payment}{method}",
-            name = $name,
-            payment = concat_payment!($payment.payment),
-            method = format_args!(
-                "{phone}{number}{name}{cvv}{expiry}{line1}{line2}{city}{province}{country}{token}",
-                phone = $method_args.phone,
-                number = $method_args.number,
-                name = $method_args.name,
-                cvv = $method_args.cvv,
-                expiry = $method_args.expiry,
-                line1 = $method_args.line1,
-                line2 = $method_args.line2,
-                city = $method_args.city,
-                province = $method_args.province,
-                country = $method_args.country,
-                token = $method_args.token,
-                ),
-        )
-    }
-}
-
 use super::error::Error as PaymentError;
 use super::Submit;
-use crate::{status, Client, Error, Hash, Payload};
+use crate::retry::is_transient;
+use crate::status::Update;
+use crate::test_mode::{self, Scenario};
+use crate::{status, transport, Client, Config, Error, Hash};
 use async_trait::async_trait;
 use celes::Country;
 use secrecy::Secret;
@@ -73,6 +52,24 @@ impl<'a> Method<'a> {
         Method::OneMoney { phone }
     }
 
+    /// Construct `EcoCash` payment method with the test mode phone number of
+    /// a scenario
+    #[must_use]
+    pub fn test_eco_cash(scenario: Scenario) -> Self {
+        Method::EcoCash {
+            phone: scenario.phone(),
+        }
+    }
+
+    /// Construct `OneMoney` payment method with the test mode phone number of
+    /// a scenario
+    #[must_use]
+    pub fn test_one_money(scenario: Scenario) -> Self {
+        Method::OneMoney {
+            phone: scenario.phone(),
+        }
+    }
+
     /// Construct Visa/MasterCard payment method
     #[must_use]
     pub fn vmc(card: Card<'a>, address: Address<'a>, token: &'a str) -> Self {
@@ -84,49 +81,6 @@ impl<'a> Method<'a> {
     }
 }
 
-#[derive(Default)]
-struct MethodArgs<'a> {
-    phone: &'a str,
-    number: &'a str,
-    name: &'a str,
-    cvv: &'a str,
-    expiry: &'a str,
-    line1: &'a str,
-    line2: &'a str,
-    city: &'a str,
-    province: &'a str,
-    country: &'a str,
-    token: &'a str,
-}
-
-impl<'a> From<&'a Method<'a>> for MethodArgs<'a> {
-    fn from(method: &'a Method<'a>) -> Self {
-        match method {
-            Method::EcoCash { phone } | Method::OneMoney { phone } => Self {
-                phone,
-                ..Default::default()
-            },
-            Method::VisaOrMastercard {
-                card,
-                address,
-                token,
-            } => Self {
-                token,
-                number: card.number,
-                name: card.name,
-                cvv: card.cvv,
-                expiry: card.expiry,
-                line1: address.line1,
-                line2: address.line2.unwrap_or(""),
-                city: address.city,
-                province: address.province.unwrap_or(""),
-                country: address.country.long_name,
-                ..Default::default()
-            },
-        }
-    }
-}
-
 /// Card message
 #[derive(Debug, Clone, Serialize)]
 pub struct Card<'a> {
@@ -176,6 +130,62 @@ impl<'a> Payment<'a> {
         self.payment.tokenize = Some(tokenize);
         self
     }
+
+    /// Get merchant trace
+    #[must_use]
+    pub fn merchant_trace(&self) -> Option<&str> {
+        self.payment.merchant_trace
+    }
+
+    /// Submit the payment, recovering the transaction if the outcome of the
+    /// request is unknown
+    ///
+    /// When sending the request or reading the response fails, or a proxy in
+    /// front of Paynow answers with a `5xx` status, Paynow may still have
+    /// received the payment and prompted the customer. Instead of
+    /// initiating it again, the transaction is looked up using the merchant
+    /// trace of this payment and its status is returned.
+    ///
+    /// The payment is sent only once, even if the client's
+    /// [`RetryPolicy`](crate::retry::RetryPolicy) retries initiation.
+    ///
+    /// # Errors
+    ///
+    /// Returns the original error if Paynow has no record of the payment.
+    /// That isn't proof that the payment never reached Paynow, which may
+    /// still be processing it, so it should only be submitted again once a
+    /// later trace still doesn't find it. If the lookup itself fails its
+    /// error is returned and the state of the payment is still unknown.
+    pub async fn submit_or_recover(&self, client: &Client) -> Result<Outcome, Error> {
+        match self.initiate(client).await {
+            Ok(response) => Ok(Outcome::Initiated(response)),
+            Err(error) => match self.payment.merchant_trace {
+                Some(merchant_trace) if is_transient(&error) => {
+                    recover(error, client.trace_payment(merchant_trace).await)
+                }
+                _ => Err(error),
+            },
+        }
+    }
+}
+
+/// Outcome of submitting a payment with recovery
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    /// The payment was initiated
+    Initiated(Response),
+    /// The payment was already known to Paynow
+    Recovered(Update),
+}
+
+pub(crate) fn recover(error: Error, trace: Result<Update, Error>) -> Result<Outcome, Error> {
+    match trace {
+        Ok(update) => Ok(Outcome::Recovered(update)),
+        // Paynow may not have received the payment or may not have processed
+        // it yet, the caller has to trace it again to tell which
+        Err(Error::NotFound(..)) => Err(error),
+        Err(trace_error) => Err(trace_error),
+    }
 }
 
 #[async_trait]
@@ -183,61 +193,61 @@ impl Submit for &'_ Payment<'_> {
     type Response = Response;
 
     async fn submit(self, client: &Client) -> Result<Self::Response, Error> {
+        client
+            .config
+            .retry
+            .run(false, || self.initiate(client))
+            .await
+    }
+}
+
+impl Payment<'_> {
+    /// Send the payment to Paynow once
+    async fn initiate(&self, client: &Client) -> Result<Response, Error> {
+        let request = self.request(&client.config)?;
+        let response = client.transport.post(request).await?;
+        self.response(&client.config, response)
+    }
+
+    pub(crate) fn request(&self, config: &Config) -> Result<transport::Request, Error> {
         #[derive(Debug, Clone, Serialize)]
         struct Msg<'a> {
             method: &'static str,
             #[serde(flatten)]
             payment: &'a Payment<'a>,
-            hash: Secret<Hash>,
         }
-        let endpoint = client
+        let endpoint = config
             .base
             .join("remotetransaction")
             .map_err(Error::InvalidExpressPaymentUrl)?;
+        test_mode::check(config, &self.payment, Some(&self.method))?;
         let payload = Msg {
             method: self.method.name(),
-            hash: client.hash(concat_express_payment!(
-                self.method.name(),
-                self,
-                MethodArgs::from(&self.method)
-            )),
             payment: self,
         };
-        let res: Response = client
-            .submit(endpoint, Payload::Form(&payload))
-            .await
-            .map_err(|err| match err {
-                Error::UnexpectedResponse(error, msg) => {
-                    match serde_urlencoded::from_str::<'_, super::error::Response>(&msg) {
-                        Ok(res) => match PaymentError::from(res) {
-                            PaymentError::InvalidId => Error::InvalidId(client.id),
-                            PaymentError::AmountOverflow => {
-                                Error::AmountOverflow(self.payment.amount)
-                            }
-                            PaymentError::InvalidAmount => {
-                                Error::InvalidAmount(self.payment.amount)
-                            }
-                            PaymentError::InsufficientBalance => Error::InsufficientBalance,
-                            PaymentError::Response(msg) => {
-                                Error::Response(reqwest::StatusCode::OK, msg)
-                            }
-                        },
-                        Err(..) => Error::UnexpectedResponse(error, msg),
-                    }
+        config.form(endpoint, &payload)
+    }
+
+    pub(crate) fn response(
+        &self,
+        config: &Config,
+        response: transport::Response,
+    ) -> Result<Response, Error> {
+        config.parse(response).map_err(|err| match err {
+            Error::UnexpectedResponse(error, msg) => {
+                match serde_urlencoded::from_str::<'_, super::error::Response>(&msg) {
+                    Ok(res) => match PaymentError::from(res) {
+                        PaymentError::InvalidId => Error::InvalidId(config.id),
+                        PaymentError::AmountOverflow => Error::AmountOverflow(self.payment.amount),
+                        PaymentError::InvalidAmount => Error::InvalidAmount(self.payment.amount),
+                        PaymentError::InsufficientBalance => Error::InsufficientBalance,
+                        PaymentError::Response(msg) => Error::Response(200, msg),
+                    },
+                    Err(..) => Error::UnexpectedResponse(error, msg),
                 }
-                error => error,
-            })?;
-        client.validate_hash(
-            &res.hash,
-            format_args!(
-                "{status}{instructions}{paynow_reference}{poll_url}",
-                status = res.status,
-                instructions = res.instructions,
-                paynow_reference = res.paynow_reference,
-                poll_url = res.poll_url
-            ),
-        )?;
-        Ok(res)
+            }
+            error => error,
+        })
     }
 }
 
@@ -284,3 +294,26 @@ impl Response {
         self.poll_url
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_errors_are_ambiguous() {
+        assert!(is_transient(&Error::Response(502, String::new())));
+        assert!(is_transient(&Error::Response(504, String::new())));
+        assert!(!is_transient(&Error::Response(400, String::new())));
+        assert!(!is_transient(&Error::HashMismatch(String::new())));
+    }
+
+    #[test]
+    fn unknown_payments_keep_the_original_error() {
+        let error = Error::Response(502, "Bad Gateway".to_owned());
+        let trace = Err(Error::NotFound("trace".to_owned()));
+        assert!(matches!(
+            recover(error, trace),
+            Err(Error::Response(502, ..))
+        ));
+    }
+}
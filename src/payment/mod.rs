@@ -1,30 +1,8 @@
 //! Payment messages
 
-#[macro_use]
-mod macros {
-    #[doc(hidden)]
-    #[macro_export]
-    macro_rules! concat_payment {
-        ($payment:expr) => {
-            format_args!("{id}{reference}{amount}{additional_info}{return_url}{result_url}{auth_email}{tokenize}{merchant_trace}{status}",
-                         id=$payment.id,
-                         reference=$payment.reference,
-                         amount=$payment.amount,
-                         additional_info=$payment.additional_info.unwrap_or(""),
-                         return_url=$payment.return_url.map(|x| x.to_string()).unwrap_or(String::new()),
-                         result_url=$payment.result_url,
-                         auth_email=$payment.auth_email.unwrap_or(""),
-                         tokenize=$payment.tokenize.map(|x| x.to_string()).unwrap_or(String::new()),
-                         merchant_trace=$payment.merchant_trace.unwrap_or(""),
-                         status=$payment.status,
-                         )
-        }
-    }
-}
-
 pub mod express;
 
-use crate::{status, Client, Error, Hash, Payload};
+use crate::{status, test_mode, transport, Client, Config, Error, Hash};
 use async_trait::async_trait;
 use error::Error as PaymentError;
 use rust_decimal::Decimal;
@@ -92,50 +70,48 @@ impl Submit for &'_ Payment<'_> {
     type Response = Response;
 
     async fn submit(self, client: &Client) -> Result<Self::Response, Error> {
-        #[derive(Debug, Clone, Serialize)]
-        struct Msg<'a> {
-            #[serde(flatten)]
-            payment: &'a Payment<'a>,
-            hash: Secret<Hash>,
-        }
-        let endpoint = client
+        client
+            .config
+            .retry
+            .run(false, || async {
+                let request = self.request(&client.config)?;
+                let response = client.transport.post(request).await?;
+                self.response(&client.config, response)
+            })
+            .await
+    }
+}
+
+impl Payment<'_> {
+    pub(crate) fn request(&self, config: &Config) -> Result<transport::Request, Error> {
+        let endpoint = config
             .base
             .join("initiatetransaction")
             .map_err(Error::InvalidPaymentUrl)?;
-        let payload = Msg {
-            hash: client.hash(concat_payment!(self)),
-            payment: self,
-        };
-        let res: Response = client
-            .submit(endpoint, Payload::Form(&payload))
-            .await
-            .map_err(|err| match err {
-                Error::UnexpectedResponse(error, msg) => {
-                    match serde_urlencoded::from_str::<'_, error::Response>(&msg) {
-                        Ok(res) => match PaymentError::from(res) {
-                            PaymentError::InvalidId => Error::InvalidId(client.id),
-                            PaymentError::AmountOverflow => Error::AmountOverflow(self.amount),
-                            PaymentError::InvalidAmount => Error::InvalidAmount(self.amount),
-                            PaymentError::InsufficientBalance => Error::InsufficientBalance,
-                            PaymentError::Response(msg) => {
-                                Error::Response(reqwest::StatusCode::OK, msg)
-                            }
-                        },
-                        Err(..) => Error::UnexpectedResponse(error, msg),
-                    }
+        test_mode::check(config, self, None)?;
+        config.form(endpoint, self)
+    }
+
+    pub(crate) fn response(
+        &self,
+        config: &Config,
+        response: transport::Response,
+    ) -> Result<Response, Error> {
+        config.parse(response).map_err(|err| match err {
+            Error::UnexpectedResponse(error, msg) => {
+                match serde_urlencoded::from_str::<'_, error::Response>(&msg) {
+                    Ok(res) => match PaymentError::from(res) {
+                        PaymentError::InvalidId => Error::InvalidId(config.id),
+                        PaymentError::AmountOverflow => Error::AmountOverflow(self.amount),
+                        PaymentError::InvalidAmount => Error::InvalidAmount(self.amount),
+                        PaymentError::InsufficientBalance => Error::InsufficientBalance,
+                        PaymentError::Response(msg) => Error::Response(200, msg),
+                    },
+                    Err(..) => Error::UnexpectedResponse(error, msg),
                 }
-                error => error,
-            })?;
-        client.validate_hash(
-            &res.hash,
-            format_args!(
-                "{status}{browser_url}{poll_url}",
-                status = res.status,
-                browser_url = res.browser_url,
-                poll_url = res.poll_url
-            ),
-        )?;
-        Ok(res)
+            }
+            error => error,
+        })
     }
 }
 
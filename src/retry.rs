@@ -0,0 +1,251 @@
+//! Retry policy
+//!
+//! Polling and tracing a payment don't change any state on Paynow so they are
+//! retried automatically according to the [`RetryPolicy`] configured on the
+//! client. Initiating a payment is only retried when
+//! [`RetryPolicy::retry_initiation`] is enabled because a request that timed
+//! out may still have reached Paynow.
+
+use crate::Error;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Future returned by a [`RetryPolicy::sleep`] function
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Retry policy with exponential backoff
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: u32,
+    jitter: bool,
+    initiation: bool,
+    retryable: fn(&Error) -> bool,
+    sleep: fn(Duration) -> Sleep,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts with jittered backoff starting at 200 milliseconds
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2,
+            jitter: true,
+            initiation: false,
+            retryable: is_transient,
+            sleep,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create a policy that never retries
+    #[must_use]
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Set the maximum number of attempts, including the first one
+    pub fn max_attempts(&mut self, attempts: u32) -> &mut Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the delay before the first retry
+    pub fn initial_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    /// Set the upper bound of the delay between attempts
+    pub fn max_backoff(&mut self, backoff: Duration) -> &mut Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Set the factor the delay is multiplied by after each attempt
+    pub fn multiplier(&mut self, multiplier: u32) -> &mut Self {
+        self.multiplier = multiplier.max(1);
+        self
+    }
+
+    /// Randomise each delay to between half and all of its computed value
+    pub fn jitter(&mut self, jitter: bool) -> &mut Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Also retry payment initiation
+    ///
+    /// Retrying an initiation that failed after Paynow received it can
+    /// initiate the payment twice, so this is disabled by default.
+    pub fn retry_initiation(&mut self, retry: bool) -> &mut Self {
+        self.initiation = retry;
+        self
+    }
+
+    /// Set which errors are retried
+    ///
+    /// Defaults to [`is_transient`].
+    pub fn retry_if(&mut self, retryable: fn(&Error) -> bool) -> &mut Self {
+        self.retryable = retryable;
+        self
+    }
+
+    /// Set how the async client waits between attempts
+    ///
+    /// Defaults to [`sleep`], which works with any executor.
+    pub fn sleep(&mut self, sleep: fn(Duration) -> Sleep) -> &mut Self {
+        self.sleep = sleep;
+        self
+    }
+
+    fn attempts(&self, idempotent: bool) -> u32 {
+        if idempotent || self.initiation {
+            self.max_attempts
+        } else {
+            1
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.saturating_pow(attempt.saturating_sub(1));
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff);
+        if self.jitter {
+            let half = backoff / 2;
+            half + half.mul_f64(fastrand::f64())
+        } else {
+            backoff
+        }
+    }
+
+    pub(crate) async fn run<T, F, Fut>(&self, idempotent: bool, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let attempts = self.attempts(idempotent);
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Err(error) if attempt < attempts && (self.retryable)(&error) => {
+                    (self.sleep)(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    #[cfg(feature = "blocking")]
+    pub(crate) fn run_blocking<T, F>(&self, idempotent: bool, mut op: F) -> Result<T, Error>
+    where
+        F: FnMut() -> Result<T, Error>,
+    {
+        let attempts = self.attempts(idempotent);
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Err(error) if attempt < attempts && (self.retryable)(&error) => {
+                    std::thread::sleep(self.backoff(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether an error is likely to go away on its own
+///
+/// This is the case for errors sending the request or reading the response
+/// and for `5xx` responses.
+#[must_use]
+pub fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::SendingRequest(..) | Error::GettingText(..) => true,
+        Error::Response(status, ..) => *status >= 500,
+        _ => false,
+    }
+}
+
+/// Wait for `duration`
+///
+/// Uses a runtime-neutral timer so it works with any executor, including a
+/// Tokio runtime built without timers, and never panics for lack of one.
+#[must_use]
+pub fn sleep(duration: Duration) -> Sleep {
+    Box::pin(futures_timer::Delay::new(duration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_without_a_runtime() {
+        let mut policy = RetryPolicy::default();
+        policy.initial_backoff(Duration::from_millis(10));
+        let mut calls = 0;
+        let result = futures_executor::block_on(policy.run(true, || {
+            calls += 1;
+            let result = if calls == 1 {
+                Err(Error::Response(503, String::new()))
+            } else {
+                Ok(calls)
+            };
+            async move { result }
+        }));
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_within_a_runtime() {
+        let mut policy = RetryPolicy::default();
+        policy.initial_backoff(Duration::from_millis(10));
+        let mut calls = 0;
+        let result = policy
+            .run(true, || {
+                calls += 1;
+                let result = if calls == 1 {
+                    Err(Error::Response(503, String::new()))
+                } else {
+                    Ok(calls)
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[test]
+    fn retries_within_a_runtime_without_timers() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut policy = RetryPolicy::default();
+        policy.initial_backoff(Duration::from_millis(10));
+        let mut calls = 0;
+        let result = runtime.block_on(policy.run(true, || {
+            calls += 1;
+            let result = if calls == 1 {
+                Err(Error::Response(503, String::new()))
+            } else {
+                Ok(calls)
+            };
+            async move { result }
+        }));
+        assert_eq!(result.unwrap(), 2);
+    }
+}
@@ -1,12 +1,15 @@
 //! Status related messages
 
-use crate::{Client, Hash};
+use crate::form::Fields;
+use crate::{Client, Config, KeyMatch};
 use rust_decimal::Decimal;
-use secrecy::Secret;
 use serde::de::{self, Unexpected, Visitor};
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::fmt;
-use time::{format_description, Date};
+use std::str::FromStr;
+use time::format_description::StaticFormatDescription;
+use time::Date;
 use url::Url;
 
 /// Whenever the status of a transaction is changed, for example payment made,
@@ -14,17 +17,64 @@ use url::Url;
 /// The message will be sent as an HTTP POST to the resulturl specified by the
 /// merchant when the transaction initiation occurred.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Fields", into = "Fields")]
 pub struct Update {
     reference: String,
-    #[serde(rename = "paynowreference")]
     paynow_reference: u64,
     amount: Decimal,
     status: Status,
-    #[serde(rename = "pollurl")]
     poll_url: Url,
-    #[serde(flatten)]
     token: Option<Token>,
-    hash: Secret<Hash>,
+    fields: Fields,
+}
+
+impl TryFrom<Fields> for Update {
+    type Error = serde_urlencoded::de::Error;
+
+    fn try_from(fields: Fields) -> Result<Self, Self::Error> {
+        #[derive(Deserialize)]
+        struct Msg {
+            reference: String,
+            #[serde(rename = "paynowreference")]
+            paynow_reference: u64,
+            amount: Decimal,
+            status: Status,
+            #[serde(rename = "pollurl")]
+            poll_url: Url,
+            token: Option<String>,
+            #[serde(rename = "tokenexpiry")]
+            token_expiry: Option<String>,
+        }
+        let msg: Msg = fields.deserialize()?;
+        // parsed here rather than as a flattened `Option<Token>`, which would
+        // silently drop a token whose expiry isn't in the expected format
+        let token = match (msg.token, msg.token_expiry) {
+            (Some(token), Some(expiry)) => {
+                let expiry = Date::parse(&expiry, TOKEN_EXPIRY).map_err(|_| {
+                    de::Error::invalid_value(Unexpected::Str(&expiry), &"a date like 05Mar2026")
+                })?;
+                Some(Token { token, expiry })
+            }
+            (None, None) => None,
+            (Some(..), None) => return Err(de::Error::missing_field("tokenexpiry")),
+            (None, Some(..)) => return Err(de::Error::missing_field("token")),
+        };
+        Result::Ok(Self {
+            fields,
+            reference: msg.reference,
+            paynow_reference: msg.paynow_reference,
+            amount: msg.amount,
+            status: msg.status,
+            poll_url: msg.poll_url,
+            token,
+        })
+    }
+}
+
+impl From<Update> for Fields {
+    fn from(update: Update) -> Self {
+        update.fields
+    }
 }
 
 impl Update {
@@ -52,10 +102,10 @@ impl Update {
         self.amount
     }
 
-    /// Get status
+    /// Get a reference to the status
     #[must_use]
-    pub fn status(&self) -> Status {
-        self.status
+    pub fn status(&self) -> &Status {
+        &self.status
     }
 
     /// Get a reference to the poll URL
@@ -82,33 +132,128 @@ impl Update {
         self.token
     }
 
-    /// Validate status update
+    /// Validate status update, returning the integration key that signed it
     ///
     /// # Errors
     ///
     /// Returns an error if the hash is invalid
-    #[allow(clippy::missing_panics_doc)]
-    pub fn validate(&self, client: &Client) -> Result<(), crate::Error> {
-        let format = format_description::parse("[day][month repr:short][year]").unwrap();
-        client.validate_hash(
-            &self.hash,
-            format_args!(
-                "{reference}{paynow_reference}{amount}{status}{poll_url}{token}",
-                reference = self.reference,
-                paynow_reference = self.paynow_reference,
-                amount = self.amount,
-                status = self.status,
-                poll_url = self.poll_url,
-                token = match &self.token {
-                    Some(x) => format!(
-                        "{token}{expiry}",
-                        token = x.token,
-                        expiry = x.expiry.format(&format)?
-                    ),
-                    None => String::new(),
-                },
-            ),
-        )
+    pub fn validate(&self, client: &Client) -> Result<KeyMatch, crate::Error> {
+        self.validate_with(&client.config)
+    }
+
+    pub(crate) fn validate_with(&self, config: &Config) -> Result<KeyMatch, crate::Error> {
+        config.verify(&self.fields.pairs())
+    }
+}
+
+/// A transaction tracked from the status updates Paynow sends for it
+///
+/// Updates from the result URL and from polling can arrive out of order.
+/// Applying them to a transaction only moves it forward through the
+/// [`Status`] lifecycle so that, for example, a late `Sent` update doesn't
+/// overwrite `Paid`.
+///
+/// While the transaction is in an [`Unknown`](Status::Unknown) status,
+/// updates are checked against the last status it was known to be in, so
+/// that a paid transaction can't go back to pending by way of one.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    update: Update,
+    last_known: Option<Status>,
+}
+
+impl Transaction {
+    /// Start tracking a transaction from its first update
+    #[must_use]
+    pub fn new(update: Update) -> Self {
+        let last_known = known(update.status());
+        Self { update, last_known }
+    }
+
+    /// Get a reference to the current status
+    #[must_use]
+    pub fn status(&self) -> &Status {
+        self.update.status()
+    }
+
+    /// Get a reference to the update that set the current status
+    #[must_use]
+    pub fn update(&self) -> &Update {
+        &self.update
+    }
+
+    /// Consume the update that set the current status
+    #[must_use]
+    pub fn into_update(self) -> Update {
+        self.update
+    }
+
+    /// Apply an update, returning the transition that took effect
+    ///
+    /// Updates that would move the transaction to a status it can't reach
+    /// from the current one are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the update is for a different transaction
+    pub fn apply(&mut self, update: Update) -> Result<Transition, crate::Error> {
+        if update.paynow_reference() != self.update.paynow_reference() {
+            return Err(crate::Error::UnrelatedUpdate(update.paynow_reference()));
+        }
+        let current = self.status().clone();
+        let received = update.status().clone();
+        let allowed = match (&current, &self.last_known) {
+            (Status::Unknown(..), Some(last_known)) => {
+                *last_known == received || last_known.can_transition_to(&received)
+            }
+            _ => current.can_transition_to(&received),
+        };
+        let last_known = known(&received).or_else(|| self.last_known.clone());
+        let transition = if current == received {
+            Transition::Unchanged(current)
+        } else if allowed {
+            Transition::Changed {
+                from: current,
+                to: received,
+            }
+        } else {
+            return Result::Ok(Transition::Ignored { current, received });
+        };
+        self.last_known = last_known;
+        self.update = update;
+        Result::Ok(transition)
+    }
+}
+
+fn known(status: &Status) -> Option<Status> {
+    match status {
+        Status::Unknown(..) => None,
+        status => Some(status.clone()),
+    }
+}
+
+impl From<Update> for Transaction {
+    fn from(update: Update) -> Self {
+        Self::new(update)
+    }
+}
+
+/// Effect of applying an update to a [`Transaction`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transition {
+    /// Status changed
+    Changed { from: Status, to: Status },
+    /// Update has the same status as the transaction
+    Unchanged(Status),
+    /// Update was ignored because the transaction can't go back to its status
+    Ignored { current: Status, received: Status },
+}
+
+impl Transition {
+    /// Whether the status changed
+    #[must_use]
+    pub fn is_changed(&self) -> bool {
+        matches!(self, Transition::Changed { .. })
     }
 }
 
@@ -118,7 +263,6 @@ pub(crate) struct MerchantTrace<'a> {
     #[serde(rename = "merchanttrace")]
     pub(crate) merchant_trace: &'a str,
     pub(crate) status: Message,
-    pub(crate) hash: Secret<Hash>,
 }
 
 /// The following fields will be returned in the status response message only if
@@ -138,10 +282,16 @@ pub(crate) struct MerchantTrace<'a> {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     token: String,
-    #[serde(rename = "tokenexpiry")]
+    #[serde(rename = "tokenexpiry", with = "token_expiry")]
     expiry: Date,
 }
 
+/// Format Paynow sends token expiry dates in, for example `05Mar2026`
+pub(crate) const TOKEN_EXPIRY: StaticFormatDescription =
+    time::macros::format_description!("[day][month repr:short][year]");
+
+time::serde::format_description!(token_expiry, Date, TOKEN_EXPIRY);
+
 impl Token {
     /// Get a reference to the token
     #[must_use]
@@ -163,21 +313,34 @@ impl Token {
 }
 
 /// Payment status
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+///
+/// A transaction starts out as [`Created`](Status::Created) and is
+/// [`Sent`](Status::Sent) once the customer is referred to an upstream system.
+/// Once the customer pays it becomes [`Paid`](Status::Paid), possibly via
+/// [`AwaitingDelivery`](Status::AwaitingDelivery) and
+/// [`Delivered`](Status::Delivered) when the merchant has to confirm delivery
+/// first. A paid transaction can be [`Disputed`](Status::Disputed) and
+/// [`Refunded`](Status::Refunded). A transaction that has not been paid can
+/// end up [`Failed`](Status::Failed), [`Expired`](Status::Expired) or
+/// [`Cancelled`](Status::Cancelled).
+///
+/// Statuses Paynow introduces after this version are kept as
+/// [`Unknown`](Status::Unknown) so that the update they come with can still be
+/// verified and handled.
+///
+/// Statuses are ordered by how far along this lifecycle they are, with the
+/// ones a transaction fails with last, followed by unknown statuses.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Status {
     /// Transaction has been created in Paynow, but has not yet been paid by
     /// the customer.
     Created,
-    /// The transaction has been cancelled in Paynow and may not be resumed and
-    /// needs to be recreated.
-    Cancelled,
     /// Transaction has been created in Paynow and an up stream system, the
     /// customer has been referred to that upstream system but has not yet made
     /// payment.
     Sent,
     /// Transaction paid successfully, but is sitting in suspense waiting on
     /// the merchant to confirm delivery of the goods.
-    #[serde(rename = "Awaiting Delivery")]
     AwaitingDelivery,
     /// The user or merchant has acknowledged delivery of the goods but the
     /// funds are still sitting in suspense awaiting the 24 hour confirmation
@@ -191,6 +354,92 @@ pub enum Status {
     Disputed,
     /// Funds were refunded back to the customer.
     Refunded,
+    /// The customer's payment failed and the transaction needs to be
+    /// recreated.
+    Failed,
+    /// The customer did not pay in time and the transaction needs to be
+    /// recreated.
+    Expired,
+    /// The transaction has been cancelled in Paynow and may not be resumed and
+    /// needs to be recreated.
+    Cancelled,
+    /// A status this version doesn't know about, as sent by Paynow
+    Unknown(String),
+}
+
+impl Status {
+    /// Whether the customer has paid, even if the funds are still held in
+    /// suspense pending delivery
+    #[must_use]
+    pub fn is_paid(&self) -> bool {
+        matches!(
+            self,
+            Status::AwaitingDelivery | Status::Delivered | Status::Paid
+        )
+    }
+
+    /// Whether the customer has yet to pay
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        matches!(self, Status::Created | Status::Sent)
+    }
+
+    /// Whether the status will not change anymore in the normal course of
+    /// the transaction
+    ///
+    /// A paid transaction can still be disputed by the customer.
+    #[must_use]
+    pub fn is_final(&self) -> bool {
+        self.is_failure() || matches!(self, Status::Paid | Status::Refunded)
+    }
+
+    /// Whether the transaction ended without the customer paying
+    #[must_use]
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Status::Failed | Status::Expired | Status::Cancelled)
+    }
+
+    /// Whether the merchant has to confirm delivery of the goods before the
+    /// funds are released
+    #[must_use]
+    pub fn requires_delivery_confirmation(&self) -> bool {
+        matches!(self, Status::AwaitingDelivery)
+    }
+
+    /// Whether a transaction can go from this status to `next`
+    ///
+    /// Statuses are allowed to be skipped, for example a transaction can go
+    /// from `Created` straight to `Paid`, but never back to an earlier one
+    /// except for a dispute being resolved in the merchant's favour.
+    ///
+    /// Since nothing is known about an [`Unknown`](Status::Unknown) status, a
+    /// transaction can go to one from any status that isn't final and from
+    /// one to any other status. [`Transaction`] narrows the latter down using
+    /// the status the transaction was in before.
+    #[must_use]
+    pub fn can_transition_to(&self, next: &Status) -> bool {
+        use Status::{
+            AwaitingDelivery, Cancelled, Created, Delivered, Disputed, Expired, Failed, Paid,
+            Refunded, Sent, Unknown,
+        };
+
+        if let Unknown(..) = next {
+            return !self.is_final() && self != next;
+        }
+        matches!(
+            (self, next),
+            (Unknown(..), _)
+                | (Created, Sent)
+                | (
+                    Created | Sent,
+                    AwaitingDelivery | Delivered | Paid | Failed | Expired | Cancelled
+                )
+                | (AwaitingDelivery, Delivered | Paid | Disputed | Refunded)
+                | (Delivered | Disputed, Paid | Refunded)
+                | (Delivered | Paid, Disputed)
+                | (Paid, Refunded)
+        )
+    }
 }
 
 impl fmt::Display for Status {
@@ -207,11 +456,72 @@ impl fmt::Display for Status {
                 Status::Cancelled => "Cancelled",
                 Status::Disputed => "Disputed",
                 Status::Refunded => "Refunded",
+                Status::Failed => "Failed",
+                Status::Expired => "Expired",
+                Status::Unknown(status) => status,
             }
         )
     }
 }
 
+impl FromStr for Status {
+    type Err = Infallible;
+
+    /// Parse a status as sent by Paynow, falling back to
+    /// [`Unknown`](Status::Unknown)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Result::Ok(match s {
+            "Paid" => Status::Paid,
+            "Awaiting Delivery" => Status::AwaitingDelivery,
+            "Delivered" => Status::Delivered,
+            "Created" => Status::Created,
+            "Sent" => Status::Sent,
+            "Cancelled" => Status::Cancelled,
+            "Disputed" => Status::Disputed,
+            "Refunded" => Status::Refunded,
+            "Failed" => Status::Failed,
+            "Expired" => Status::Expired,
+            status => Status::Unknown(status.to_owned()),
+        })
+    }
+}
+
+impl Serialize for Status {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Status {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct StatusVisitor;
+
+        impl Visitor<'_> for StatusVisitor {
+            type Value = Status;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "a payment status")
+            }
+
+            fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                let Result::Ok(status) = s.parse();
+                Result::Ok(status)
+            }
+        }
+
+        deserializer.deserialize_str(StatusVisitor)
+    }
+}
+
 macro_rules! status {
     ($name:ident) => {
         #[derive(Debug, Clone, Copy)]
@@ -271,3 +581,237 @@ status!(Error);
 status!(Ok);
 
 status!(NotFound);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::key;
+    use crate::{ClientBuilder, HASH};
+    use time::macros::date;
+
+    const TOKENIZED: [(&str, &str); 7] = [
+        ("reference", "Order 1"),
+        ("paynowreference", "12345"),
+        ("amount", "10.00"),
+        ("status", "Paid"),
+        (
+            "pollurl",
+            "https://www.paynow.co.zw/Interface/CheckPayment/?guid=1",
+        ),
+        ("token", "6f1c2d0a-3b4e-4c5d-8e9f-0a1b2c3d4e5f"),
+        ("tokenexpiry", "05Mar2026"),
+    ];
+
+    fn tokenized(config: &Config) -> String {
+        let mut fields = TOKENIZED.to_vec();
+        let hash = config.sign(&fields);
+        fields.push((HASH, &hash));
+        serde_urlencoded::to_string(fields).unwrap()
+    }
+
+    fn config() -> Config {
+        ClientBuilder::new(1, key()).config()
+    }
+
+    #[test]
+    fn parses_token_expiry() {
+        let body = tokenized(&config());
+        let update = Update::try_from(Fields::parse(body.as_bytes())).unwrap();
+        let token = update.token().unwrap().clone();
+        assert_eq!(token.token(), "6f1c2d0a-3b4e-4c5d-8e9f-0a1b2c3d4e5f");
+        assert_eq!(token.expiry(), date!(2026 - 03 - 05));
+    }
+
+    #[test]
+    fn tokenized_update_validates() {
+        let config = config();
+        let body = tokenized(&config);
+        let update = Update::try_from(Fields::parse(body.as_bytes())).unwrap();
+        assert_eq!(update.validate_with(&config).unwrap(), KeyMatch::Primary);
+    }
+
+    #[test]
+    fn rejects_malformed_token_expiry() {
+        let config = config();
+        let mut fields = TOKENIZED.to_vec();
+        fields[6] = ("tokenexpiry", "2026-03-05");
+        let hash = config.sign(&fields);
+        fields.push((HASH, &hash));
+        let body = serde_urlencoded::to_string(fields).unwrap();
+        assert!(matches!(
+            config.parse_status_update(body.as_bytes()),
+            Err(crate::Error::MalformedUpdate(..))
+        ));
+    }
+
+    #[test]
+    fn serializes_token_expiry() {
+        let body = tokenized(&config());
+        let update = Update::try_from(Fields::parse(body.as_bytes())).unwrap();
+        let token = serde_urlencoded::to_string(update.token().unwrap()).unwrap();
+        assert!(token.ends_with("&tokenexpiry=05Mar2026"), "{token}");
+        let update = serde_urlencoded::to_string(&update).unwrap();
+        assert!(update.contains("&tokenexpiry=05Mar2026&"), "{update}");
+    }
+
+    #[test]
+    fn classifies_statuses() {
+        // status, paid, pending, final, failure, requires delivery confirmation
+        let cases = [
+            (Status::Created, false, true, false, false, false),
+            (Status::Sent, false, true, false, false, false),
+            (Status::AwaitingDelivery, true, false, false, false, true),
+            (Status::Delivered, true, false, false, false, false),
+            (Status::Paid, true, false, true, false, false),
+            (Status::Disputed, false, false, false, false, false),
+            (Status::Refunded, false, false, true, false, false),
+            (Status::Failed, false, false, true, true, false),
+            (Status::Expired, false, false, true, true, false),
+            (Status::Cancelled, false, false, true, true, false),
+            (
+                Status::Unknown("Pending Review".to_owned()),
+                false,
+                false,
+                false,
+                false,
+                false,
+            ),
+        ];
+        for (status, paid, pending, r#final, failure, delivery) in &cases {
+            assert_eq!(
+                (
+                    status.is_paid(),
+                    status.is_pending(),
+                    status.is_final(),
+                    status.is_failure(),
+                    status.requires_delivery_confirmation(),
+                ),
+                (*paid, *pending, *r#final, *failure, *delivery),
+                "{status}"
+            );
+        }
+        // the cases are listed in lifecycle order
+        assert!(cases.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    fn update(paynow_reference: u64, status: &str) -> Update {
+        let mut fields = Fields::default();
+        fields.push("reference", "Order 1".to_owned());
+        fields.push("paynowreference", paynow_reference.to_string());
+        fields.push("amount", "10.00".to_owned());
+        fields.push("status", status.to_owned());
+        fields.push(
+            "pollurl",
+            "https://www.paynow.co.zw/Interface/CheckPayment/?guid=1".to_owned(),
+        );
+        Update::try_from(fields).unwrap()
+    }
+
+    #[test]
+    fn applies_transitions() {
+        let review = || Status::Unknown("Pending Review".to_owned());
+        let cases = [
+            // a late update can't move a transaction back
+            (
+                "Paid",
+                "Sent",
+                Transition::Ignored {
+                    current: Status::Paid,
+                    received: Status::Sent,
+                },
+            ),
+            // statuses Paynow never sent can be skipped
+            (
+                "Created",
+                "Paid",
+                Transition::Changed {
+                    from: Status::Created,
+                    to: Status::Paid,
+                },
+            ),
+            (
+                "Paid",
+                "Disputed",
+                Transition::Changed {
+                    from: Status::Paid,
+                    to: Status::Disputed,
+                },
+            ),
+            (
+                "Disputed",
+                "Paid",
+                Transition::Changed {
+                    from: Status::Disputed,
+                    to: Status::Paid,
+                },
+            ),
+            (
+                "Pending Review",
+                "Paid",
+                Transition::Changed {
+                    from: review(),
+                    to: Status::Paid,
+                },
+            ),
+            (
+                "Sent",
+                "Pending Review",
+                Transition::Changed {
+                    from: Status::Sent,
+                    to: review(),
+                },
+            ),
+            (
+                "Paid",
+                "Pending Review",
+                Transition::Ignored {
+                    current: Status::Paid,
+                    received: review(),
+                },
+            ),
+            ("Sent", "Sent", Transition::Unchanged(Status::Sent)),
+        ];
+        for (current, received, expected) in cases {
+            let mut transaction = Transaction::new(update(1, current));
+            let transition = transaction.apply(update(1, received)).unwrap();
+            assert_eq!(transition, expected, "{current} to {received}");
+            let status = match &transition {
+                Transition::Ignored { current, .. } => current,
+                Transition::Changed { to, .. } => to,
+                Transition::Unchanged(status) => status,
+            };
+            assert_eq!(transaction.status(), status);
+        }
+
+        // an unknown status doesn't let a paid transaction go back to pending
+        let mut transaction = Transaction::new(update(1, "Awaiting Delivery"));
+        assert!(transaction
+            .apply(update(1, "Pending Review"))
+            .unwrap()
+            .is_changed());
+        assert_eq!(
+            transaction.apply(update(1, "Sent")).unwrap(),
+            Transition::Ignored {
+                current: review(),
+                received: Status::Sent,
+            }
+        );
+        assert_eq!(
+            transaction.apply(update(1, "Paid")).unwrap(),
+            Transition::Changed {
+                from: review(),
+                to: Status::Paid,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unrelated_updates() {
+        let mut transaction = Transaction::new(update(1, "Sent"));
+        assert!(matches!(
+            transaction.apply(update(2, "Paid")),
+            Err(crate::Error::UnrelatedUpdate(2))
+        ));
+        assert_eq!(transaction.status(), &Status::Sent);
+    }
+}
@@ -0,0 +1,187 @@
+//! Paynow test mode
+//!
+//! While an integration is in test mode Paynow doesn't move any money.
+//! Express payments only accept the phone numbers of the [`Scenario`]s it
+//! simulates, and the auth email has to be the email the merchant logs in to
+//! Paynow with, for web payments too.
+//!
+//! Setting the [`Environment`] the integration is in with
+//! [`ClientBuilder::environment`](crate::ClientBuilder::environment) makes the
+//! client enforce these rules before sending a payment, so test numbers are
+//! never sent to a live integration and real numbers never to a test one.
+
+use crate::payment::express::Method;
+use crate::payment::Payment;
+use crate::{Config, Error};
+
+/// Mode of the integration a client talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Environment {
+    /// Integration is in test mode
+    Test,
+    /// Integration is live and payments are real
+    Live,
+}
+
+/// Outcome simulated by Paynow in test mode, selected by the phone number of
+/// an express payment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scenario {
+    /// Payment succeeds after about 5 seconds
+    Success,
+    /// Payment succeeds after about 30 seconds
+    DelayedSuccess,
+    /// Customer cancels the payment after about 30 seconds
+    Cancelled,
+    /// Payment fails immediately because the customer has insufficient balance
+    InsufficientBalance,
+}
+
+impl Scenario {
+    /// All scenarios
+    pub const ALL: [Scenario; 4] = [
+        Scenario::Success,
+        Scenario::DelayedSuccess,
+        Scenario::Cancelled,
+        Scenario::InsufficientBalance,
+    ];
+
+    /// Get the phone number that selects this scenario
+    #[must_use]
+    pub fn phone(self) -> &'static str {
+        match self {
+            Scenario::Success => "0771111111",
+            Scenario::DelayedSuccess => "0772222222",
+            Scenario::Cancelled => "0773333333",
+            Scenario::InsufficientBalance => "0774444444",
+        }
+    }
+
+    /// Get the scenario selected by a phone number
+    ///
+    /// Numbers in international format, with or without a leading `+`, are
+    /// recognised too.
+    #[must_use]
+    pub fn from_phone(phone: &str) -> Option<Self> {
+        let digits: String = phone.chars().filter(|c| !c.is_whitespace()).collect();
+        let digits = digits.strip_prefix('+').unwrap_or(&digits);
+        let local = match digits.strip_prefix("263") {
+            Some(number) => format!("0{number}"),
+            None => digits.to_owned(),
+        };
+        Self::ALL
+            .into_iter()
+            .find(|scenario| scenario.phone() == local)
+    }
+}
+
+/// Check that a payment follows the rules of the environment the client is
+/// configured for
+pub(crate) fn check(
+    config: &Config,
+    payment: &Payment<'_>,
+    method: Option<&Method<'_>>,
+) -> Result<(), Error> {
+    let Some(environment) = config.environment else {
+        return Ok(());
+    };
+    let phone = match method {
+        Some(Method::EcoCash { phone } | Method::OneMoney { phone }) => Some(*phone),
+        _ => None,
+    };
+    match environment {
+        Environment::Live => match phone {
+            Some(phone) if Scenario::from_phone(phone).is_some() => {
+                Err(Error::TestNumberInLive(phone.to_owned()))
+            }
+            _ => Ok(()),
+        },
+        Environment::Test => {
+            if let Some(phone) = phone {
+                if Scenario::from_phone(phone).is_none() {
+                    return Err(Error::LiveNumberInTest(phone.to_owned()));
+                }
+            }
+            // a client can't be built for the test environment without a
+            // merchant email and a payment without an auth email can't match it
+            let email = payment.auth_email.unwrap_or_default();
+            match &config.merchant_email {
+                Some(merchant) if merchant.eq_ignore_ascii_case(email) => Ok(()),
+                _ => Err(Error::TestAuthEmail(email.to_owned())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::key;
+    use crate::ClientBuilder;
+    use rust_decimal::Decimal;
+    use url::Url;
+
+    const MERCHANT: &str = "merchant@example.com";
+
+    fn builder(environment: Environment) -> ClientBuilder {
+        let mut builder = ClientBuilder::new(1, key());
+        builder.environment(environment);
+        builder
+    }
+
+    #[test]
+    fn test_environment_requires_merchant_email() {
+        let config = builder(Environment::Test).config();
+        let url = Url::parse("https://example.com/result").unwrap();
+        let payment = config.payment("1", Decimal::ONE, &url, &url);
+        assert!(matches!(
+            check(&config, &payment, None),
+            Err(Error::TestAuthEmail(..))
+        ));
+        assert!(matches!(
+            builder(Environment::Test).build(),
+            Err(Error::MissingMerchantEmail)
+        ));
+    }
+
+    #[test]
+    fn test_payments_require_merchant_auth_email() {
+        let config = builder(Environment::Test).merchant_email(MERCHANT).config();
+        let url = Url::parse("https://example.com/result").unwrap();
+        let mut payment = config.payment("1", Decimal::ONE, &url, &url);
+        assert!(matches!(
+            check(&config, &payment, None),
+            Err(Error::TestAuthEmail(..))
+        ));
+        payment.auth_email("someone@example.com");
+        assert!(matches!(
+            check(&config, &payment, None),
+            Err(Error::TestAuthEmail(..))
+        ));
+        payment.auth_email("Merchant@Example.com");
+        assert!(check(&config, &payment, None).is_ok());
+    }
+
+    #[test]
+    fn numbers_match_environment() {
+        let url = Url::parse("https://example.com/result").unwrap();
+        let test = Method::test_eco_cash(Scenario::Success);
+        let live = Method::eco_cash("0779999999");
+
+        let config = builder(Environment::Test).merchant_email(MERCHANT).config();
+        let mut payment = config.payment("1", Decimal::ONE, &url, &url);
+        payment.auth_email(MERCHANT);
+        assert!(check(&config, &payment, Some(&test)).is_ok());
+        assert!(matches!(
+            check(&config, &payment, Some(&live)),
+            Err(Error::LiveNumberInTest(..))
+        ));
+
+        let config = builder(Environment::Live).config();
+        assert!(check(&config, &payment, Some(&live)).is_ok());
+        assert!(matches!(
+            check(&config, &payment, Some(&test)),
+            Err(Error::TestNumberInLive(..))
+        ));
+    }
+}
@@ -0,0 +1,61 @@
+//! Helpers shared by the unit tests
+
+use crate::{ApiKey, Key};
+use uuid::Uuid;
+
+/// Random integration key
+pub(crate) fn key() -> ApiKey {
+    ApiKey::new(Key::from(Uuid::new_v4()))
+}
+
+/// Client that fails any request sent to Paynow
+#[cfg(any(
+    feature = "actix",
+    feature = "axum",
+    feature = "listener",
+    feature = "testing",
+    feature = "tower"
+))]
+pub(crate) fn offline_client(key: ApiKey) -> crate::Client {
+    use crate::transport::{Error, Request, Response, Transport};
+    use async_trait::async_trait;
+
+    #[derive(Debug)]
+    struct Offline;
+
+    #[async_trait]
+    impl Transport for Offline {
+        async fn post(&self, _: Request) -> Result<Response, Error> {
+            Err(Error::Send("offline".into()))
+        }
+    }
+
+    crate::Client::builder(1, key)
+        .transport(Offline)
+        .build()
+        .unwrap()
+}
+
+/// Body of a paid status update signed for `client`
+#[cfg(any(
+    feature = "actix",
+    feature = "axum",
+    feature = "listener",
+    feature = "tower"
+))]
+pub(crate) fn update_body(client: &crate::Client) -> String {
+    let fields = [
+        ("reference", "Order 1"),
+        ("paynowreference", "1"),
+        ("amount", "10.00"),
+        ("status", "Paid"),
+        (
+            "pollurl",
+            "https://www.paynow.co.zw/Interface/CheckPayment/?guid=1",
+        ),
+    ];
+    let hash = client.sign(&fields);
+    let mut fields = fields.to_vec();
+    fields.push((crate::HASH, &hash));
+    serde_urlencoded::to_string(fields).unwrap()
+}
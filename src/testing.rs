@@ -0,0 +1,365 @@
+//! Builders for signed Paynow messages
+//!
+//! Status updates and responses can only be obtained from Paynow, which makes
+//! code that handles them hard to test. The builders in this module produce
+//! them, and the raw form bodies Paynow would send, signed with a given
+//! integration key. Messages can also be deliberately mis-signed with
+//! [`Signature`] to test that they are rejected.
+
+use crate::form::Fields;
+use crate::payment::{self, express};
+use crate::status::{self, Status, Update};
+use crate::{transport, ApiKey, ClientBuilder, Error, Key, HASH};
+use rust_decimal::Decimal;
+use secrecy::{ExposeSecret, Secret};
+use time::Date;
+use url::Url;
+use uuid::Uuid;
+
+/// How a message is signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Signature {
+    /// Signed with the integration key
+    #[default]
+    Valid,
+    /// Signed with a different key
+    WrongKey,
+    /// Not signed at all
+    Missing,
+}
+
+/// Builder for status updates
+#[derive(Debug, Clone)]
+pub struct UpdateBuilder {
+    key: ApiKey,
+    signature: Signature,
+    reference: String,
+    paynow_reference: u64,
+    amount: Decimal,
+    status: Status,
+    poll_url: Url,
+    token: Option<(String, Date)>,
+}
+
+impl UpdateBuilder {
+    /// Create a builder for a paid update signed with `key`
+    #[must_use]
+    pub fn new(key: ApiKey) -> Self {
+        Self {
+            key,
+            signature: Signature::Valid,
+            reference: "1".to_owned(),
+            paynow_reference: 1,
+            amount: Decimal::new(100, 2),
+            status: Status::Paid,
+            poll_url: default_poll_url(),
+            token: None,
+        }
+    }
+
+    /// Set how the update is signed
+    pub fn signature(&mut self, signature: Signature) -> &mut Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Set reference
+    pub fn reference(&mut self, reference: impl Into<String>) -> &mut Self {
+        self.reference = reference.into();
+        self
+    }
+
+    /// Set Paynow reference
+    pub fn paynow_reference(&mut self, reference: u64) -> &mut Self {
+        self.paynow_reference = reference;
+        self
+    }
+
+    /// Set amount
+    pub fn amount(&mut self, amount: Decimal) -> &mut Self {
+        self.amount = amount;
+        self
+    }
+
+    /// Set status
+    pub fn status(&mut self, status: Status) -> &mut Self {
+        self.status = status;
+        self
+    }
+
+    /// Set poll URL
+    pub fn poll_url(&mut self, url: Url) -> &mut Self {
+        self.poll_url = url;
+        self
+    }
+
+    /// Set token and its expiry date
+    pub fn token(&mut self, token: impl Into<String>, expiry: Date) -> &mut Self {
+        self.token = Some((token.into(), expiry));
+        self
+    }
+
+    /// Get the form body Paynow would post to the result URL
+    #[must_use]
+    pub fn body(&self) -> String {
+        self.fields().encode()
+    }
+
+    /// Build the update
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fields can't be parsed back into an update,
+    /// for example when the year of the token expiry has more than four digits
+    pub fn build(&self) -> Result<Update, Error> {
+        Update::try_from(self.fields()).map_err(Error::MalformedUpdate)
+    }
+
+    fn fields(&self) -> Fields {
+        let mut fields = Fields::default();
+        fields.push("reference", self.reference.clone());
+        fields.push("paynowreference", self.paynow_reference.to_string());
+        fields.push("amount", self.amount.to_string());
+        fields.push("status", self.status.to_string());
+        fields.push("pollurl", self.poll_url.to_string());
+        if let Some((token, expiry)) = &self.token {
+            fields.push("token", token.clone());
+            // the format only has components a date has so this should never panic
+            fields.push("tokenexpiry", expiry.format(status::TOKEN_EXPIRY).unwrap());
+        }
+        sign(&self.key, self.signature, fields)
+    }
+}
+
+/// Builder for responses to payments
+#[derive(Debug, Clone)]
+pub struct PaymentResponseBuilder {
+    key: ApiKey,
+    signature: Signature,
+    browser_url: Url,
+    poll_url: Url,
+}
+
+impl PaymentResponseBuilder {
+    /// Create a builder for a response signed with `key`
+    #[must_use]
+    pub fn new(key: ApiKey) -> Self {
+        Self {
+            key,
+            signature: Signature::Valid,
+            // we know this is a valid URL so this should never panic
+            browser_url: Url::parse(concat!(
+                "https://www.paynow.co.zw/Payment/ConfirmPayment/1",
+                "?guid=00000000-0000-0000-0000-000000000000",
+            ))
+            .unwrap(),
+            poll_url: default_poll_url(),
+        }
+    }
+
+    /// Set how the response is signed
+    pub fn signature(&mut self, signature: Signature) -> &mut Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Set browser URL
+    pub fn browser_url(&mut self, url: Url) -> &mut Self {
+        self.browser_url = url;
+        self
+    }
+
+    /// Set poll URL
+    pub fn poll_url(&mut self, url: Url) -> &mut Self {
+        self.poll_url = url;
+        self
+    }
+
+    /// Get the body Paynow would respond with
+    #[must_use]
+    pub fn body(&self) -> String {
+        self.fields().encode()
+    }
+
+    /// Get the response as a transport would return it
+    #[must_use]
+    pub fn response(&self) -> transport::Response {
+        ok(self.body())
+    }
+
+    /// Build the response
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response is not signed
+    pub fn build(&self) -> Result<payment::Response, Error> {
+        parse(self.body())
+    }
+
+    fn fields(&self) -> Fields {
+        let mut fields = Fields::default();
+        fields.push("status", "Ok".to_owned());
+        fields.push("browserurl", self.browser_url.to_string());
+        fields.push("pollurl", self.poll_url.to_string());
+        sign(&self.key, self.signature, fields)
+    }
+}
+
+/// Builder for responses to express payments
+#[derive(Debug, Clone)]
+pub struct ExpressResponseBuilder {
+    key: ApiKey,
+    signature: Signature,
+    instructions: String,
+    paynow_reference: u64,
+    poll_url: Url,
+}
+
+impl ExpressResponseBuilder {
+    /// Create a builder for a response signed with `key`
+    #[must_use]
+    pub fn new(key: ApiKey) -> Self {
+        Self {
+            key,
+            signature: Signature::Valid,
+            instructions: "Dial *151*2*7# and enter your PIN to approve the payment".to_owned(),
+            paynow_reference: 1,
+            poll_url: default_poll_url(),
+        }
+    }
+
+    /// Set how the response is signed
+    pub fn signature(&mut self, signature: Signature) -> &mut Self {
+        self.signature = signature;
+        self
+    }
+
+    /// Set instructions
+    pub fn instructions(&mut self, instructions: impl Into<String>) -> &mut Self {
+        self.instructions = instructions.into();
+        self
+    }
+
+    /// Set Paynow reference
+    pub fn paynow_reference(&mut self, reference: u64) -> &mut Self {
+        self.paynow_reference = reference;
+        self
+    }
+
+    /// Set poll URL
+    pub fn poll_url(&mut self, url: Url) -> &mut Self {
+        self.poll_url = url;
+        self
+    }
+
+    /// Get the body Paynow would respond with
+    #[must_use]
+    pub fn body(&self) -> String {
+        self.fields().encode()
+    }
+
+    /// Get the response as a transport would return it
+    #[must_use]
+    pub fn response(&self) -> transport::Response {
+        ok(self.body())
+    }
+
+    /// Build the response
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the response is not signed
+    pub fn build(&self) -> Result<express::Response, Error> {
+        parse(self.body())
+    }
+
+    fn fields(&self) -> Fields {
+        let mut fields = Fields::default();
+        fields.push("status", "Ok".to_owned());
+        fields.push("instructions", self.instructions.clone());
+        fields.push("paynowreference", self.paynow_reference.to_string());
+        fields.push("pollurl", self.poll_url.to_string());
+        sign(&self.key, self.signature, fields)
+    }
+}
+
+fn default_poll_url() -> Url {
+    // we know this is a valid URL so this should never panic
+    Url::parse(concat!(
+        "https://www.paynow.co.zw/Interface/CheckPayment/",
+        "?guid=00000000-0000-0000-0000-000000000000",
+    ))
+    .unwrap()
+}
+
+fn sign(key: &ApiKey, signature: Signature, mut fields: Fields) -> Fields {
+    let key = match signature {
+        Signature::Valid => key.clone(),
+        Signature::WrongKey => other_key(key),
+        Signature::Missing => return fields,
+    };
+    let hash = ClientBuilder::new(0, key).config().sign(&fields.pairs());
+    fields.push(HASH, hash);
+    fields
+}
+
+/// A key that is guaranteed to be different from `key`
+fn other_key(key: &ApiKey) -> ApiKey {
+    let other = Key::from(Uuid::from_u128(1));
+    if *key.expose_secret() == other {
+        Secret::new(Key::from(Uuid::from_u128(2)))
+    } else {
+        Secret::new(other)
+    }
+}
+
+fn ok(body: String) -> transport::Response {
+    transport::Response { status: 200, body }
+}
+
+fn parse<O: serde::de::DeserializeOwned>(body: String) -> Result<O, Error> {
+    serde_urlencoded::from_str(&body).map_err(|error| Error::UnexpectedResponse(error, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, offline_client};
+    use time::macros::date;
+
+    fn parse(signature: Signature) -> Result<Update, Error> {
+        let key = key();
+        let body = UpdateBuilder::new(key.clone()).signature(signature).body();
+        offline_client(key).parse_status_update(body.as_bytes())
+    }
+
+    #[test]
+    fn signs_updates() {
+        let update = parse(Signature::Valid).unwrap();
+        assert_eq!(update.status(), &Status::Paid);
+    }
+
+    #[test]
+    fn mis_signs_updates() {
+        assert!(matches!(
+            parse(Signature::WrongKey),
+            Err(Error::HashMismatch(..))
+        ));
+        assert!(matches!(parse(Signature::Missing), Err(Error::MissingHash)));
+    }
+
+    #[test]
+    fn round_trips_token_expiry() {
+        let key = key();
+        let body = UpdateBuilder::new(key.clone())
+            .token("token", date!(2026 - 03 - 05))
+            .body();
+        assert!(body.contains("&tokenexpiry=05Mar2026&"), "{body}");
+        let update = offline_client(key)
+            .parse_status_update(body.as_bytes())
+            .unwrap();
+        let token = update.take_token().unwrap();
+        assert_eq!(token.token(), "token");
+        assert_eq!(token.expiry(), date!(2026 - 03 - 05));
+    }
+}
@@ -0,0 +1,190 @@
+//! [tower] integration
+//!
+//! [tower]: https://docs.rs/tower
+
+use crate::status::Update;
+use crate::webhook::{self, reply};
+use crate::Client;
+use http::{Method, Request, Response, StatusCode};
+use http_body::Body;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Layer that verifies status updates posted by Paynow to the result URL
+#[derive(Debug, Clone)]
+pub struct WebhookLayer {
+    client: Client,
+}
+
+impl WebhookLayer {
+    /// Create new layer verifying updates with `client`
+    #[must_use]
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+}
+
+impl<S> Layer<S> for WebhookLayer {
+    type Service = Webhook<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Webhook {
+            inner,
+            client: self.client.clone(),
+        }
+    }
+}
+
+/// Service that verifies status updates posted by Paynow to the result URL
+///
+/// Verified updates are forwarded to the inner service. Requests that are not
+/// `POST`s are answered with `405 Method Not Allowed`, bodies larger than
+/// 16 KiB with `413 Payload Too Large`, malformed bodies with
+/// `400 Bad Request` and bodies with an invalid signature with
+/// `401 Unauthorized`, without calling the inner service.
+#[derive(Debug, Clone)]
+pub struct Webhook<S> {
+    client: Client,
+    inner: S,
+}
+
+impl<S> Webhook<S> {
+    /// Create new service verifying updates with `client`
+    #[must_use]
+    pub fn new(client: Client, inner: S) -> Self {
+        Self { client, inner }
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Webhook<S>
+where
+    S: Service<Update, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqBody: Body + Send + 'static,
+    ReqBody::Data: Send,
+    ReqBody::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let client = self.client.clone();
+        // the inner service was driven to readiness by `poll_ready` so we use
+        // it and leave a fresh clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            if req.method() != Method::POST {
+                return Ok(reply(StatusCode::METHOD_NOT_ALLOWED));
+            }
+            match webhook::read_update(&client, req.into_body()).await {
+                Ok(update) => inner.call(update).await,
+                Err(rejected) => Ok(reply(rejected.status())),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{key, offline_client, update_body};
+    use crate::webhook::MAX_BODY;
+    use http_body_util::Full;
+    use std::collections::VecDeque;
+    use std::convert::Infallible;
+    use std::sync::{Arc, Mutex};
+
+    /// Inner service that accepts every update, keeping their references
+    #[derive(Debug, Clone, Default)]
+    struct Accept(Arc<Mutex<Vec<String>>>);
+
+    impl Service<Update> for Accept {
+        type Response = Response<String>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, update: Update) -> Self::Future {
+            self.0.lock().unwrap().push(update.take_reference());
+            std::future::ready(Ok(Response::default()))
+        }
+    }
+
+    /// Status the webhook answers `request` with and the references of the
+    /// updates that reached the inner service
+    fn call(client: Client, request: Request<Full<VecDeque<u8>>>) -> (StatusCode, Vec<String>) {
+        let accept = Accept::default();
+        let mut webhook = Webhook::new(client, accept.clone());
+        let response = futures_executor::block_on(webhook.call(request)).unwrap();
+        let references = accept.0.lock().unwrap().clone();
+        (response.status(), references)
+    }
+
+    fn post(body: impl Into<Vec<u8>>) -> Request<Full<VecDeque<u8>>> {
+        Request::post("/result")
+            .body(Full::new(VecDeque::from(body.into())))
+            .unwrap()
+    }
+
+    #[test]
+    fn forwards_verified_updates() {
+        let client = offline_client(key());
+        let request = post(update_body(&client));
+        assert_eq!(
+            call(client, request),
+            (StatusCode::OK, vec!["Order 1".to_owned()])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_updates() {
+        let request = post("status=Paid");
+        assert_eq!(
+            call(offline_client(key()), request),
+            (StatusCode::BAD_REQUEST, Vec::new())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        let request = post(update_body(&offline_client(key())));
+        assert_eq!(
+            call(offline_client(key()), request),
+            (StatusCode::UNAUTHORIZED, Vec::new())
+        );
+    }
+
+    #[test]
+    fn rejects_other_methods() {
+        let client = offline_client(key());
+        let request = Request::get("/result")
+            .body(Full::new(VecDeque::from(update_body(&client).into_bytes())))
+            .unwrap();
+        assert_eq!(
+            call(client, request),
+            (StatusCode::METHOD_NOT_ALLOWED, Vec::new())
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_bodies() {
+        let request = post(vec![b'a'; MAX_BODY + 1]);
+        assert_eq!(
+            call(offline_client(key()), request),
+            (StatusCode::PAYLOAD_TOO_LARGE, Vec::new())
+        );
+    }
+}
@@ -0,0 +1,78 @@
+//! HTTP transport
+
+use async_trait::async_trait;
+use std::fmt;
+use url::Url;
+
+/// Boxed error returned by transports
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Request to be sent to Paynow
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// Endpoint the request is sent to
+    pub url: Url,
+    /// URL encoded form body, `None` for requests without a body
+    pub form: Option<String>,
+}
+
+/// Response received from Paynow
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// HTTP status code
+    pub status: u16,
+    /// Response body
+    pub body: String,
+}
+
+impl Response {
+    /// Whether the status code is in the `2xx` range
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Error returned by a transport
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("failed to send request")]
+    Send(#[source] BoxError),
+    #[error("failed to read response body")]
+    Body(#[source] BoxError),
+}
+
+/// HTTP transport used by [`Client`](crate::Client) to talk to Paynow
+///
+/// Requests are always sent as an HTTP `POST`. When the request has a form
+/// body it must be sent with the `application/x-www-form-urlencoded` content
+/// type, otherwise an empty body must be sent.
+#[async_trait]
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Send a request and return the response
+    async fn post(&self, request: Request) -> Result<Response, Error>;
+}
+
+#[cfg(feature = "reqwest")]
+#[async_trait]
+impl Transport for reqwest::Client {
+    async fn post(&self, request: Request) -> Result<Response, Error> {
+        use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+
+        let mut builder = reqwest::Client::post(self, request.url);
+        builder = match request.form {
+            Some(form) => builder
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(form),
+            None => builder.header(CONTENT_LENGTH, 0),
+        };
+        let response = builder.send().await.map_err(|e| Error::Send(Box::new(e)))?;
+        let status = response.status().as_u16();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::Body(Box::new(e)))?;
+        Ok(Response { status, body })
+    }
+}
@@ -0,0 +1,266 @@
+//! Watch a payment until it is paid or reaches a final status
+//!
+//! See [`Client::watch`](crate::Client::watch).
+
+use crate::retry::{self, is_transient, Sleep};
+use crate::status::{Status, Update};
+use crate::{Client, Error};
+use futures_core::Stream;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// How often a payment is polled and for how long
+#[derive(Debug, Clone)]
+pub struct WatchPolicy {
+    interval: Duration,
+    max_interval: Duration,
+    multiplier: u32,
+    timeout: Duration,
+    sleep: fn(Duration) -> Sleep,
+}
+
+impl Default for WatchPolicy {
+    /// Poll every 5 seconds for up to 5 minutes
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_interval: Duration::from_secs(30),
+            multiplier: 1,
+            timeout: Duration::from_secs(300),
+            sleep: retry::sleep,
+        }
+    }
+}
+
+impl WatchPolicy {
+    /// Set the delay before the second poll
+    pub fn interval(&mut self, interval: Duration) -> &mut Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Set the upper bound of the delay between polls
+    pub fn max_interval(&mut self, interval: Duration) -> &mut Self {
+        self.max_interval = interval;
+        self
+    }
+
+    /// Set the factor the delay is multiplied by after each poll
+    pub fn multiplier(&mut self, multiplier: u32) -> &mut Self {
+        self.multiplier = multiplier.max(1);
+        self
+    }
+
+    /// Set how long to wait for the payment to be paid or reach a final
+    /// status
+    ///
+    /// A timeout too large to represent means waiting forever.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Set how to wait between polls
+    ///
+    /// Defaults to [`retry::sleep`], which works with any executor.
+    pub fn sleep(&mut self, sleep: fn(Duration) -> Sleep) -> &mut Self {
+        self.sleep = sleep;
+        self
+    }
+}
+
+struct Watch {
+    client: Client,
+    poll_url: Url,
+    policy: WatchPolicy,
+    deadline: Option<Instant>,
+    interval: Option<Duration>,
+    last: Option<Status>,
+}
+
+impl Watch {
+    /// Wait until the next poll is due, returning `false` when the deadline
+    /// has passed instead
+    async fn wait(&mut self) -> bool {
+        let remaining = match self.deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+            None => Duration::MAX,
+        };
+        if remaining.is_zero() {
+            return false;
+        }
+        match self.interval {
+            Some(interval) => {
+                (self.policy.sleep)(interval.min(remaining)).await;
+                let next = interval
+                    .saturating_mul(self.policy.multiplier)
+                    .min(self.policy.max_interval);
+                self.interval = Some(next);
+                match self.deadline {
+                    Some(deadline) => Instant::now() < deadline,
+                    None => true,
+                }
+            }
+            None => {
+                self.interval = Some(self.policy.interval);
+                true
+            }
+        }
+    }
+}
+
+pub(crate) fn watch(
+    client: Client,
+    poll_url: Url,
+    policy: WatchPolicy,
+) -> impl Stream<Item = Result<Update, Error>> + Send + Unpin {
+    let state = Watch {
+        client,
+        poll_url,
+        deadline: Instant::now().checked_add(policy.timeout),
+        policy,
+        interval: None,
+        last: None,
+    };
+    Box::pin(futures_util::stream::unfold(
+        Some(state),
+        |state| async move {
+            let mut state = state?;
+            loop {
+                if !state.wait().await {
+                    return Some((Err(Error::WatchTimeout), None));
+                }
+                match state.client.poll_status(state.poll_url.clone()).await {
+                    Ok(update) => {
+                        let status = update.status();
+                        if status.is_paid() || status.is_final() {
+                            return Some((Ok(update), None));
+                        }
+                        if state.last.as_ref() != Some(status) {
+                            state.last = Some(status.clone());
+                            return Some((Ok(update), Some(state)));
+                        }
+                    }
+                    Err(error) if is_transient(&error) => return Some((Err(error), Some(state))),
+                    Err(error) => return Some((Err(error), None)),
+                }
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::key;
+    use crate::transport::{self, Request, Response, Transport};
+    use crate::{ClientBuilder, Config, HASH};
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+    use std::sync::Mutex;
+
+    /// Transport answering polls with the given statuses in turn, repeating
+    /// the last one
+    #[derive(Debug)]
+    struct Statuses {
+        config: Config,
+        statuses: Mutex<Vec<&'static str>>,
+    }
+
+    #[async_trait]
+    impl Transport for Statuses {
+        async fn post(&self, _: Request) -> Result<Response, transport::Error> {
+            let mut statuses = self.statuses.lock().unwrap();
+            let status = if statuses.len() > 1 {
+                statuses.remove(0)
+            } else {
+                statuses[0]
+            };
+            let mut fields = vec![
+                ("reference", "1"),
+                ("paynowreference", "1"),
+                ("amount", "1.00"),
+                ("status", status),
+                (
+                    "pollurl",
+                    "https://www.paynow.co.zw/Interface/CheckPayment/?guid=1",
+                ),
+            ];
+            let hash = self.config.sign(&fields);
+            fields.push((HASH, &hash));
+            Ok(Response {
+                status: 200,
+                body: serde_urlencoded::to_string(&fields).unwrap(),
+            })
+        }
+    }
+
+    fn statuses(policy: &WatchPolicy, statuses: Vec<&'static str>) -> Vec<Result<Status, Error>> {
+        let key = key();
+        let client = Client::builder(1, key.clone())
+            .transport(Statuses {
+                config: ClientBuilder::new(1, key).config(),
+                statuses: Mutex::new(statuses),
+            })
+            .build()
+            .unwrap();
+        let poll_url =
+            Url::parse("https://www.paynow.co.zw/Interface/CheckPayment/?guid=1").unwrap();
+        let stream = client.watch(poll_url, policy.clone());
+        futures_executor::block_on(
+            stream
+                .map(|update| update.map(|update| update.status().clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn stops_once_paid() {
+        let mut policy = WatchPolicy::default();
+        policy
+            .interval(Duration::from_millis(1))
+            .timeout(Duration::from_secs(5));
+        let received = statuses(
+            &policy,
+            vec!["Sent", "Sent", "Awaiting Delivery", "Delivered"],
+        );
+        assert!(matches!(
+            received.as_slice(),
+            [Ok(Status::Sent), Ok(Status::AwaitingDelivery)]
+        ));
+    }
+
+    #[test]
+    fn unrepresentable_timeout_waits_forever() {
+        let mut policy = WatchPolicy::default();
+        policy
+            .interval(Duration::from_millis(1))
+            .timeout(Duration::MAX);
+        let received = statuses(&policy, vec!["Created", "Paid"]);
+        assert!(matches!(
+            received.as_slice(),
+            [Ok(Status::Created), Ok(Status::Paid)]
+        ));
+    }
+
+    #[test]
+    fn times_out_without_a_final_status() {
+        let mut policy = WatchPolicy::default();
+        policy
+            .interval(Duration::from_millis(1))
+            .timeout(Duration::from_millis(50));
+        let received = statuses(&policy, vec!["Created", "Sent"]);
+        // the customer never responds, so `Sent` is yielded only once
+        assert!(
+            matches!(
+                received.as_slice(),
+                [
+                    Ok(Status::Created),
+                    Ok(Status::Sent),
+                    Err(Error::WatchTimeout)
+                ]
+            ),
+            "{received:?}"
+        );
+    }
+}
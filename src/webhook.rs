@@ -0,0 +1,59 @@
+//! Request handling shared by the result URL front ends
+
+use crate::status::Update;
+use crate::{Client, Error};
+use http::StatusCode;
+use http_body::Body;
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+
+/// Largest status update body accepted, real ones are well under a kilobyte
+pub(crate) const MAX_BODY: usize = 16 * 1024;
+
+/// Why a result URL request was rejected
+#[derive(Debug)]
+pub(crate) enum Rejected {
+    /// Body couldn't be read, answered with the status
+    Body(StatusCode),
+    /// Body doesn't hold a valid status update
+    Update(Error),
+}
+
+impl Rejected {
+    /// Status Paynow is answered with
+    #[cfg(any(feature = "listener", feature = "tower"))]
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            Self::Body(status) => *status,
+            Self::Update(error) if error.is_signature_error() => StatusCode::UNAUTHORIZED,
+            Self::Update(..) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+/// Read a result URL request body and verify the status update in it
+///
+/// Bodies larger than [`MAX_BODY`] are rejected with `413 Payload Too Large`,
+/// malformed ones with `400 Bad Request` and ones with an invalid signature
+/// with `401 Unauthorized`.
+pub(crate) async fn read_update<B>(client: &Client, body: B) -> Result<Update, Rejected>
+where
+    B: Body,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let body = match Limited::new(body, MAX_BODY).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(error) if error.is::<LengthLimitError>() => {
+            return Err(Rejected::Body(StatusCode::PAYLOAD_TOO_LARGE))
+        }
+        Err(..) => return Err(Rejected::Body(StatusCode::BAD_REQUEST)),
+    };
+    client.parse_status_update(&body).map_err(Rejected::Update)
+}
+
+/// Empty response with `status`
+#[cfg(any(feature = "listener", feature = "tower"))]
+pub(crate) fn reply<B: Default>(status: StatusCode) -> http::Response<B> {
+    let mut response = http::Response::new(B::default());
+    *response.status_mut() = status;
+    response
+}